@@ -0,0 +1,49 @@
+//! Interactive tools for individual days, which don't fit the part1/part2 runner.
+//!
+//! ```text
+//! cargo run --bin tools -- day05 input.txt --crane 9001
+//! cargo run --bin tools -- day07 input.txt du -n 5
+//! ```
+
+use aoc_2022::{day05, day07};
+use clap::Parser;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+#[derive(Parser)]
+#[command(name = "tools")]
+enum Tools {
+    /// Step through the crane program of a day 5 input, reading commands from stdin
+    Day05 {
+        input: PathBuf,
+
+        #[arg(long, value_enum, default_value = "9000")]
+        crane: day05::Crane,
+    },
+
+    /// Query the filesystem described by a day 7 transcript
+    Day07 {
+        input: PathBuf,
+
+        #[command(subcommand)]
+        query: day07::Query,
+    },
+}
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    match Tools::parse() {
+        Tools::Day05 { input, crane } => {
+            let input = fs::read_to_string(input)?;
+            let program = day05::parse(&input);
+            day05::repl(&program, crane, io::stdin().lock(), io::stdout().lock())?;
+        }
+        Tools::Day07 { input, query } => {
+            let input = fs::read_to_string(input)?;
+            let fs = day07::parse(&input)?;
+            print!("{}", fs.query(&query));
+        }
+    }
+
+    Ok(())
+}
@@ -1,12 +1,73 @@
 use crate::util::get_2_mut;
+use std::fmt::{self, Display, Write};
+use std::io::{self, BufRead};
 
-pub type Crate = u8;
+/// A crate label, stored inline. Standard puzzle inputs only use single letters.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Crate {
+    len: u8,
+    label: [u8; Crate::MAX_LEN],
+}
+
+impl Crate {
+    pub const MAX_LEN: usize = 15;
+
+    /// Create a crate from a label, returning None if it's empty or longer than [Crate::MAX_LEN]
+    pub fn new(label: &str) -> Option<Self> {
+        if label.is_empty() || label.len() > Self::MAX_LEN {
+            return None;
+        }
+
+        let mut crate_ = Crate {
+            len: label.len() as u8,
+            label: [0; Self::MAX_LEN],
+        };
+        crate_.label[..label.len()].copy_from_slice(label.as_bytes());
+        Some(crate_)
+    }
+
+    pub fn as_str(&self) -> &str {
+        std::str::from_utf8(&self.label[..self.len as usize]).expect("label is valid UTF-8")
+    }
+}
+
+impl TryFrom<u8> for Crate {
+    type Error = u8;
+
+    /// Create a crate with a single-character label. Fails if the byte isn't ASCII.
+    fn try_from(label: u8) -> Result<Self, u8> {
+        if !label.is_ascii() {
+            return Err(label);
+        }
+
+        let mut crate_ = Crate {
+            len: 1,
+            label: [0; Self::MAX_LEN],
+        };
+        crate_.label[0] = label;
+        Ok(crate_)
+    }
+}
+
+impl Display for Crate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl fmt::Debug for Crate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "[{}]", self.as_str())
+    }
+}
 
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub struct Input {
     stacks: Vec<Vec<Crate>>,
     instructions: Vec<Instruction>,
 }
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub struct Instruction {
     amount: usize,
     from: usize,
@@ -16,18 +77,7 @@ pub struct Instruction {
 pub fn parse(input: &str) -> Input {
     let (stacks, instructions) = input.split_once("\n\n").unwrap();
 
-    let mut stack_iter = stacks.lines().rev();
-    let stack_count = stack_iter.next().unwrap().as_bytes().chunks(4).count();
-    let mut stacks = vec![vec![]; stack_count];
-
-    for stack in stack_iter {
-        for (i, item) in stack.as_bytes().chunks(4).enumerate() {
-            let item = item[1];
-            if item != b' ' {
-                stacks[i].push(item);
-            }
-        }
-    }
+    let stacks = parse_standard_stacks(stacks).unwrap_or_else(|| parse_stacks(stacks));
 
     let instructions = instructions
         .lines()
@@ -49,13 +99,80 @@ pub fn parse(input: &str) -> Input {
     }
 }
 
+/// Parse a drawing where every line is padded to the same width, and every stack is a column of
+/// exactly 4 bytes containing single-letter crates.
+///
+/// Returns None if the footer doesn't number the stacks `1..=n` in the middle of those columns,
+/// which is the case for drawings with 10 or more stacks.
+fn parse_standard_stacks(drawing: &str) -> Option<Vec<Vec<Crate>>> {
+    let mut lines = drawing.lines().rev();
+    let footer = lines.next()?;
+    let stack_count = footer.len() / 4 + 1;
+    let standard_footer = (1..=stack_count)
+        .map(|i| format!(" {i} "))
+        .collect::<Vec<_>>()
+        .join(" ");
+    if footer != standard_footer {
+        return None;
+    }
+    let mut stacks = vec![vec![]; stack_count];
+
+    for line in lines {
+        if line.len() != footer.len() {
+            return None;
+        }
+
+        for (i, item) in line.as_bytes().chunks(4).enumerate() {
+            match item {
+                [b' ', b' ', b' ', ..] => {}
+                [b'[', item, b']', ..] if item.is_ascii_alphabetic() => {
+                    stacks[i].push(Crate::try_from(*item).ok()?)
+                }
+                _ => return None,
+            }
+        }
+    }
+
+    Some(stacks)
+}
+
+/// Parse a drawing with labels of any width, and where lines may have their trailing spaces
+/// trimmed. A crate belongs to the stack whose number in the footer lies beneath it.
+fn parse_stacks(drawing: &str) -> Vec<Vec<Crate>> {
+    let mut lines = drawing.lines().rev();
+    let footer = lines.next().expect("missing stack numbers");
+    let columns: Vec<_> = (footer.split(' ').filter(|number| !number.is_empty()))
+        .map(|number| {
+            let start = number.as_ptr() as usize - footer.as_ptr() as usize;
+            start..start + number.len()
+        })
+        .collect();
+    let mut stacks = vec![vec![]; columns.len()];
+
+    for line in lines {
+        let mut start = 0;
+        while let Some(open) = line[start..].find('[') {
+            let open = start + open;
+            let close = open + line[open..].find(']').expect("unclosed crate label");
+            let label = &line[open + 1..close];
+            start = close + 1;
+
+            let stack = columns
+                .iter()
+                .position(|column| column.start <= close && open < column.end)
+                .unwrap_or_else(|| panic!("crate [{label}] isn't above any stack"));
+            let crate_ = Crate::new(label).unwrap_or_else(|| panic!("bad crate label: {label:?}"));
+            stacks[stack].push(crate_);
+        }
+    }
+
+    stacks
+}
+
 pub fn part1(input: &str) -> String {
     let mut input = parse(input);
-    for Instruction { amount, from, to } in input.instructions {
-        let [from, to] = get_2_mut(&mut input.stacks, from, to);
-        let start = from.len() - amount;
-        from[start..].reverse();
-        to.extend(from.drain(start..));
+    for instruction in input.instructions {
+        execute(&mut input.stacks, instruction, Crane::CrateMover9000);
     }
 
     display_stacks(&input.stacks)
@@ -63,21 +180,292 @@ pub fn part1(input: &str) -> String {
 
 pub fn part2(input: &str) -> String {
     let mut input = parse(input);
-
-    for Instruction { amount, from, to } in input.instructions {
-        let [from, to] = get_2_mut(&mut input.stacks, from, to);
-        let start = from.len() - amount;
-        to.extend(from.drain(start..));
+    for instruction in input.instructions {
+        execute(&mut input.stacks, instruction, Crane::CrateMover9001);
     }
 
     display_stacks(&input.stacks)
 }
 
-fn display_stacks(stacks: &Vec<Vec<Crate>>) -> String {
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum Crane {
+    /// Moves crates one at a time
+    #[value(name = "9000")]
+    CrateMover9000,
+
+    /// Moves several crates at once, retaining their order
+    #[value(name = "9001")]
+    CrateMover9001,
+}
+
+fn execute(stacks: &mut [Vec<Crate>], instruction: Instruction, crane: Crane) {
+    let Instruction { amount, from, to } = instruction;
+    let [from, to] = get_2_mut(stacks, from, to);
+    let start = from.len() - amount;
+    if crane == Crane::CrateMover9000 {
+        from[start..].reverse();
+    }
+    to.extend(from.drain(start..));
+}
+
+impl Input {
+    /// Run the first `steps` instructions, returning the resulting state along with the
+    /// instructions that remain
+    pub fn after(&self, steps: usize, crane: Crane) -> Input {
+        let steps = steps.min(self.instructions.len());
+        let mut stacks = self.stacks.clone();
+        for &instruction in &self.instructions[..steps] {
+            execute(&mut stacks, instruction, crane);
+        }
+
+        Input {
+            stacks,
+            instructions: self.instructions[steps..].to_vec(),
+        }
+    }
+}
+
+/// Steps through the instructions of an [Input], keeping an undo log so that it can also step
+/// backwards
+pub struct Executor<'a> {
+    input: &'a Input,
+    crane: Crane,
+    stacks: Vec<Vec<Crate>>,
+
+    /// The crates moved by every executed instruction, in the order they were stacked on `from`
+    undo_log: Vec<Vec<Crate>>,
+}
+
+impl<'a> Executor<'a> {
+    pub fn new(input: &'a Input, crane: Crane) -> Self {
+        Executor {
+            input,
+            crane,
+            stacks: input.stacks.clone(),
+            undo_log: Vec::with_capacity(input.instructions.len()),
+        }
+    }
+
+    /// The number of instructions that have been executed
+    pub fn step(&self) -> usize {
+        self.undo_log.len()
+    }
+
+    pub fn stacks(&self) -> &[Vec<Crate>] {
+        &self.stacks
+    }
+
+    /// The top crate of every stack, or a space for empty stacks
+    pub fn tops(&self) -> String {
+        display_stacks(&self.stacks)
+    }
+
+    /// Execute the next instruction, if there is one
+    pub fn forward(&mut self) -> Option<Instruction> {
+        let instruction = *self.input.instructions.get(self.step())?;
+        let from = &self.stacks[instruction.from];
+        let moved = from[from.len() - instruction.amount..].to_vec();
+        execute(&mut self.stacks, instruction, self.crane);
+        self.undo_log.push(moved);
+        Some(instruction)
+    }
+
+    /// Undo the last executed instruction, if there is one
+    pub fn back(&mut self) -> Option<Instruction> {
+        let moved = self.undo_log.pop()?;
+        let instruction = self.input.instructions[self.step()];
+        let to = &mut self.stacks[instruction.to];
+        to.truncate(to.len() - instruction.amount);
+        self.stacks[instruction.from].extend(moved);
+        Some(instruction)
+    }
+
+    /// Step forwards or backwards until `step` instructions have been executed
+    pub fn seek(&mut self, step: usize) {
+        while self.step() < step && self.forward().is_some() {}
+        while self.step() > step && self.back().is_some() {}
+    }
+
+    /// Find the first step which moved `item` onto `stack`, or 0 if it was there from the start
+    pub fn find_arrival(&mut self, item: Crate, stack: usize) -> Option<usize> {
+        self.seek(0);
+        if self.stacks.get(stack)?.contains(&item) {
+            return Some(0);
+        }
+
+        while let Some(instruction) = self.forward() {
+            let moved = self.undo_log.last().unwrap();
+            if instruction.to == stack && moved.contains(&item) {
+                return Some(self.step());
+            }
+        }
+
+        None
+    }
+}
+
+enum Command {
+    Next(usize),
+    Back(usize),
+    Goto(usize),
+    Show,
+    Tops(Option<usize>),
+    Find(Crate, usize),
+    Quit,
+}
+
+fn parse_command(line: &str) -> Option<Command> {
+    let words: Vec<&str> = line.split_whitespace().collect();
+    let num = |word: &str| word.parse::<usize>().ok();
+
+    Some(match words[..] {
+        ["next" | "n"] => Command::Next(1),
+        ["next" | "n", count] => Command::Next(num(count)?),
+        ["back" | "b"] => Command::Back(1),
+        ["back" | "b", count] => Command::Back(num(count)?),
+        ["goto" | "g", step] => Command::Goto(num(step)?),
+        ["show" | "s"] => Command::Show,
+        ["tops" | "t"] => Command::Tops(None),
+        ["tops" | "t", step] => Command::Tops(Some(num(step)?)),
+        ["find" | "f", item, stack] => {
+            Command::Find(Crate::new(item)?, num(stack)?.checked_sub(1)?)
+        }
+        ["quit" | "q"] => Command::Quit,
+        _ => return None,
+    })
+}
+
+/// Run an interactive debugger for a crane program, reading commands from `input` until it's
+/// exhausted or `quit` is entered
+pub fn repl(
+    program: &Input,
+    crane: Crane,
+    mut input: impl BufRead,
+    mut output: impl io::Write,
+) -> io::Result<()> {
+    let mut executor = Executor::new(program, crane);
+    let mut line = String::new();
+
+    loop {
+        write!(
+            output,
+            "[{}/{}]> ",
+            executor.step(),
+            program.instructions.len()
+        )?;
+        output.flush()?;
+
+        line.clear();
+        if input.read_line(&mut line)? == 0 {
+            return Ok(());
+        }
+
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        match parse_command(&line) {
+            Some(Command::Next(count)) => {
+                for _ in 0..count {
+                    match executor.forward() {
+                        Some(instruction) => writeln!(output, "{instruction}")?,
+                        None => break,
+                    }
+                }
+            }
+            Some(Command::Back(count)) => {
+                for _ in 0..count {
+                    match executor.back() {
+                        Some(instruction) => writeln!(output, "undo {instruction}")?,
+                        None => break,
+                    }
+                }
+            }
+            Some(Command::Goto(step)) => executor.seek(step),
+            Some(Command::Show) => write!(output, "{}", render_stacks(executor.stacks()))?,
+            Some(Command::Tops(None)) => writeln!(output, "{}", executor.tops())?,
+            Some(Command::Tops(Some(step))) => {
+                let current = executor.step();
+                executor.seek(step);
+                writeln!(output, "{}", executor.tops())?;
+                executor.seek(current);
+            }
+            Some(Command::Find(item, stack)) => {
+                let current = executor.step();
+                match executor.find_arrival(item, stack) {
+                    Some(step) => writeln!(output, "reached stack {} at step {step}", stack + 1)?,
+                    None => writeln!(output, "never reached stack {}", stack + 1)?,
+                }
+                executor.seek(current);
+            }
+            Some(Command::Quit) => return Ok(()),
+            None => writeln!(
+                output,
+                "commands: next [count], back [count], goto <step>, show, tops [step], \
+                 find <crate> <stack>, quit"
+            )?,
+        }
+    }
+}
+
+/// Render the stacks as an ASCII drawing, in the same format as the puzzle input
+pub fn render_stacks(stacks: &[Vec<Crate>]) -> String {
+    let height = stacks.iter().map(Vec::len).max().unwrap_or(0);
+    let label_w = (stacks.iter().flatten().map(|item| item.as_str().len()))
+        .chain([stacks.len().to_string().len()])
+        .max()
+        .unwrap_or(1);
+    let column_w = label_w + 2;
+    let mut out = String::new();
+
+    for level in (0..height).rev() {
+        for (i, stack) in stacks.iter().enumerate() {
+            if i != 0 {
+                out.push(' ');
+            }
+            match stack.get(level) {
+                Some(item) => write!(out, "{:column_w$}", format!("[{item}]")).unwrap(),
+                None => write!(out, "{:column_w$}", "").unwrap(),
+            }
+        }
+        out.push('\n');
+    }
+
+    for i in 0..stacks.len() {
+        if i != 0 {
+            out.push(' ');
+        }
+        write!(out, "{:^column_w$}", i + 1).unwrap();
+    }
+    out.push('\n');
+
+    out
+}
+
+impl Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let Instruction { amount, from, to } = self;
+        write!(f, "move {amount} from {} to {}", from + 1, to + 1)
+    }
+}
+
+impl Display for Input {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{}", render_stacks(&self.stacks))?;
+        for instruction in &self.instructions {
+            writeln!(f, "{instruction}")?;
+        }
+        Ok(())
+    }
+}
+
+fn display_stacks(stacks: &[Vec<Crate>]) -> String {
     let mut out = String::new();
     for stack in stacks {
-        let &item = stack.last().unwrap_or(&b' ');
-        out.push(item as char);
+        match stack.last() {
+            Some(item) => out.push_str(item.as_str()),
+            None => out.push(' '),
+        }
     }
 
     out
@@ -85,14 +473,140 @@ fn display_stacks(stacks: &Vec<Vec<Crate>>) -> String {
 
 #[cfg(test)]
 mod tests {
-    use super::{parse, part1, part2};
+    use super::{
+        display_stacks, parse, parse_stacks, parse_standard_stacks, part1, part2, repl, Crane,
+        Crate, Executor, Input,
+    };
 
     #[test]
     pub fn test_parse() {
+        let (drawing, _) = include_str!("test-input").split_once("\n\n").unwrap();
+        assert_eq!(parse_standard_stacks(drawing), Some(parse_stacks(drawing)));
+
         let input = parse(include_str!("test-input"));
+        let label = |label| Crate::try_from(label).unwrap();
         assert_eq!(
             input.stacks,
-            vec![vec![b'Z', b'N'], vec![b'M', b'C', b'D'], vec![b'P']],
+            [vec![b'Z', b'N'], vec![b'M', b'C', b'D'], vec![b'P']]
+                .map(|stack| stack.into_iter().map(label).collect::<Vec<_>>()),
+        );
+    }
+
+    #[test]
+    pub fn test_parse_many_stacks() {
+        // with 10 or more stacks the columns are wider, but with 12 stacks every line is still a
+        // whole number of 4-byte chunks, and stacks 1, 5 and 9 line up with them
+        let mut stacks = vec![vec![]; 12];
+        for (i, label) in [(0, "A"), (4, "B"), (8, "C")] {
+            stacks[i].push(Crate::new(label).unwrap());
+        }
+        let input = Input {
+            stacks,
+            instructions: vec![],
+        };
+        let text = input.to_string();
+        let (drawing, _) = text.split_once("\n\n").unwrap();
+        assert_eq!(parse_standard_stacks(drawing), None);
+        assert_eq!(parse(&text), input);
+
+        assert_eq!(Crate::try_from(0xff), Err(0xff));
+    }
+
+    #[test]
+    pub fn test_parse_labels() {
+        let input = "    [AB]\n[C] [D]  [EFG]\n 1   2    3\n\nmove 1 from 2 to 3\n";
+        let input = parse(input);
+        let label = |label| Crate::new(label).unwrap();
+        assert_eq!(
+            input.stacks,
+            [
+                vec![label("C")],
+                vec![label("D"), label("AB")],
+                vec![label("EFG")]
+            ],
+        );
+
+        let input = input.after(1, Crane::CrateMover9000);
+        assert_eq!(display_stacks(&input.stacks), "CDAB");
+        assert_eq!(
+            input.to_string(),
+            "            [AB] \n\
+             [C]   [D]   [EFG]\n\
+             \x20 1     2     3  \n\
+             \n"
+        );
+        assert_eq!(parse(&input.to_string()), input);
+    }
+
+    #[test]
+    pub fn test_render() {
+        let input = include_str!("test-input");
+        assert_eq!(parse(input).to_string(), input);
+
+        let input = parse(input).after(2, Crane::CrateMover9000);
+        assert_eq!(
+            input.to_string(),
+            "        [Z]\n\
+             \x20       [N]\n\
+             \x20   [C] [D]\n\
+             \x20   [M] [P]\n\
+             \x201   2   3 \n\
+             \n\
+             move 2 from 2 to 1\n\
+             move 1 from 1 to 2\n"
+        );
+        assert_eq!(parse(&input.to_string()), input);
+    }
+
+    #[test]
+    pub fn test_executor() {
+        let input = parse(include_str!("test-input"));
+        let mut executor = Executor::new(&input, Crane::CrateMover9000);
+
+        let mut tops = vec![executor.tops()];
+        while executor.forward().is_some() {
+            tops.push(executor.tops());
+        }
+        assert_eq!(tops, ["NDP", "DCP", " CZ", "M Z", "CMZ"]);
+
+        while executor.back().is_some() {}
+        assert_eq!(executor.stacks(), input.stacks);
+
+        executor.seek(3);
+        assert_eq!(executor.tops(), "M Z");
+        let label = |label| Crate::new(label).unwrap();
+        assert_eq!(executor.find_arrival(label("D"), 2), Some(2));
+        assert_eq!(executor.find_arrival(label("N"), 0), Some(0));
+        assert_eq!(executor.find_arrival(label("P"), 0), None);
+    }
+
+    #[test]
+    pub fn test_repl() {
+        let input = parse(include_str!("test-input"));
+        let commands = "next 2\nshow\nback\ntops 4\nfind Z 3\nfoo\nquit\nnext\n";
+        let mut output = vec![];
+        repl(
+            &input,
+            Crane::CrateMover9000,
+            commands.as_bytes(),
+            &mut output,
+        )
+        .unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "[0/4]> move 1 from 2 to 1\n\
+             move 3 from 1 to 3\n\
+             [2/4]>         [Z]\n\
+             \x20       [N]\n\
+             \x20   [C] [D]\n\
+             \x20   [M] [P]\n\
+             \x201   2   3 \n\
+             [2/4]> undo move 3 from 1 to 3\n\
+             [1/4]> CMZ\n\
+             [1/4]> reached stack 3 at step 2\n\
+             [1/4]> commands: next [count], back [count], goto <step>, show, tops [step], \
+             find <crate> <stack>, quit\n\
+             [1/4]> "
         );
     }
 
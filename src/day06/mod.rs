@@ -1,3 +1,5 @@
+use std::io;
+
 pub fn parse(input: &str) -> &[u8] {
     input.as_bytes()
 }
@@ -17,7 +19,7 @@ fn solve<const N: usize>(input: &str) -> usize {
     while let Some((i, window)) = windows.next() {
         // find the index of the first element of the last pair of duplicates that appears in the window
         let first_dup_at = (window.iter().enumerate().rev())
-            .find_map(|(j, c)| window[j + 1..].contains(c).then(|| j));
+            .find_map(|(j, c)| window[j + 1..].contains(c).then_some(j));
 
         match first_dup_at {
             // if there is a duplicate, advance the iterator until the duplicate is gone
@@ -33,9 +35,166 @@ fn solve<const N: usize>(input: &str) -> usize {
     panic!("No window of unique elements found");
 }
 
+/// Find the end index of the first window of size `n` which contains only unique elements.
+pub fn find_marker(signal: &[u8], n: usize) -> Option<usize> {
+    markers(signal, n).next()
+}
+
+/// Iterate over the end index of every window of size `n` which contains only unique elements.
+pub fn markers(signal: &[u8], n: usize) -> impl Iterator<Item = usize> + '_ {
+    let mut detector = MarkerDetector::new(n);
+    (signal.iter().enumerate())
+        .filter(move |&(_, &c)| detector.push(c))
+        .map(|(i, _)| i + 1)
+}
+
+/// Detects windows of unique elements in a stream of bytes.
+///
+/// Runs in linear time by keeping a count of every byte value in the current window.
+pub struct MarkerDetector {
+    counts: [u32; 256],
+    unique: usize,
+
+    /// Ring buffer of the last `n` bytes
+    window: Vec<u8>,
+    pushed: usize,
+}
+
+impl MarkerDetector {
+    pub fn new(n: usize) -> Self {
+        MarkerDetector {
+            counts: [0; 256],
+            unique: 0,
+            window: vec![0; n],
+            pushed: 0,
+        }
+    }
+
+    /// Push a byte into the window, returning true if the last `n` bytes are all unique.
+    pub fn push(&mut self, c: u8) -> bool {
+        let n = self.window.len();
+        if n == 0 {
+            return false;
+        }
+
+        let slot = &mut self.window[self.pushed % n];
+        if self.pushed >= n {
+            let leaving = &mut self.counts[*slot as usize];
+            *leaving -= 1;
+            if *leaving == 0 {
+                self.unique -= 1;
+            }
+        }
+        *slot = c;
+        self.pushed += 1;
+
+        let entering = &mut self.counts[c as usize];
+        *entering += 1;
+        if *entering == 1 {
+            self.unique += 1;
+        }
+
+        self.unique == n
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Marker {
+    StartOfPacket,
+    StartOfMessage,
+}
+
+/// A marker found at the absolute `offset` from the start of a stream.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MarkerEvent {
+    pub marker: Marker,
+    pub offset: u64,
+}
+
+/// Scans an [io::Read] chunk by chunk, yielding every start-of-packet and start-of-message marker.
+///
+/// The offset of a marker is the number of bytes read up to and including the marker, same as
+/// the answers to [part1] and [part2].
+pub struct MarkerStream<R> {
+    reader: R,
+    chunk: Box<[u8]>,
+    chunk_len: usize,
+    chunk_pos: usize,
+    offset: u64,
+    packet: MarkerDetector,
+    message: MarkerDetector,
+    pending: Option<MarkerEvent>,
+}
+
+impl<R: io::Read> MarkerStream<R> {
+    pub fn new(reader: R) -> Self {
+        Self::with_chunk_size(reader, 64 * 1024)
+    }
+
+    pub fn with_chunk_size(reader: R, chunk_size: usize) -> Self {
+        assert!(chunk_size > 0);
+        MarkerStream {
+            reader,
+            chunk: vec![0; chunk_size].into_boxed_slice(),
+            chunk_len: 0,
+            chunk_pos: 0,
+            offset: 0,
+            packet: MarkerDetector::new(4),
+            message: MarkerDetector::new(14),
+            pending: None,
+        }
+    }
+}
+
+impl<R: io::Read> Iterator for MarkerStream<R> {
+    type Item = io::Result<MarkerEvent>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(event) = self.pending.take() {
+            return Some(Ok(event));
+        }
+
+        loop {
+            if self.chunk_pos == self.chunk_len {
+                self.chunk_pos = 0;
+                self.chunk_len = match self.reader.read(&mut self.chunk) {
+                    Ok(0) => return None,
+                    Ok(n) => n,
+                    Err(e) if e.kind() == io::ErrorKind::Interrupted => 0,
+                    Err(e) => return Some(Err(e)),
+                };
+            }
+
+            while self.chunk_pos < self.chunk_len {
+                let c = self.chunk[self.chunk_pos];
+                self.chunk_pos += 1;
+                self.offset += 1;
+
+                let event = |marker| MarkerEvent {
+                    marker,
+                    offset: self.offset,
+                };
+                let packet = self.packet.push(c).then(|| event(Marker::StartOfPacket));
+                let message = self.message.push(c).then(|| event(Marker::StartOfMessage));
+
+                match (packet, message) {
+                    (Some(packet), message) => {
+                        self.pending = message;
+                        return Some(Ok(packet));
+                    }
+                    (None, Some(message)) => return Some(Ok(message)),
+                    (None, None) => {}
+                }
+            }
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    use super::{part1, part2};
+    use super::{find_marker, markers, part1, part2, solve, Marker, MarkerEvent, MarkerStream};
+    use crate::util::Lcg;
+    use test::Bencher;
 
     #[test]
     pub fn test_part1() {
@@ -48,4 +207,92 @@ mod tests {
         let input = include_str!("test-input");
         assert_eq!(part2(input), 19);
     }
+
+    #[test]
+    pub fn test_find_marker() {
+        let input = include_str!("test-input").as_bytes();
+        assert_eq!(find_marker(input, 4), Some(7));
+        assert_eq!(find_marker(input, 14), Some(19));
+        assert_eq!(find_marker(input, 27), None);
+        assert_eq!(find_marker(b"aaaa", 1), Some(1));
+        assert_eq!(find_marker(b"aaaa", 0), None);
+
+        assert_eq!(markers(b"abcabbcd", 3).collect::<Vec<_>>(), [3, 4, 5, 8]);
+
+        let input = signal(1000, 14);
+        assert!(solve::<14>(&input) > 1000);
+        assert_eq!(find_marker(input.as_bytes(), 14), Some(solve::<14>(&input)));
+    }
+
+    #[test]
+    pub fn test_marker_stream() {
+        let input = include_str!("test-input").as_bytes();
+        let expected: Vec<_> = (markers(input, 4).map(|i| (Marker::StartOfPacket, i)))
+            .chain(markers(input, 14).map(|i| (Marker::StartOfMessage, i)))
+            .map(|(marker, offset)| MarkerEvent {
+                marker,
+                offset: offset as u64,
+            })
+            .collect();
+
+        for chunk_size in [1, 3, 14, 4096] {
+            let mut events: Vec<_> = MarkerStream::with_chunk_size(input, chunk_size)
+                .collect::<Result<_, _>>()
+                .unwrap();
+
+            let first = |marker| events.iter().find(|event| event.marker == marker).unwrap();
+            assert_eq!(first(Marker::StartOfPacket).offset, 7);
+            assert_eq!(first(Marker::StartOfMessage).offset, 19);
+
+            events.sort_by_key(|event| event.marker == Marker::StartOfMessage);
+            assert_eq!(events, expected);
+        }
+    }
+
+    /// Generate a signal of roughly `len` bytes, where the first window of `n` unique bytes is at
+    /// the very end.
+    fn signal(len: usize, n: usize) -> String {
+        let mut rng = Lcg(0x2022_1206);
+        let mut signal: String = (0..len)
+            .map(|_| (b'a' + rng.below(n as u64 - 1) as u8) as char)
+            .collect();
+        signal.extend((b'a'..b'a' + n as u8).rev().map(char::from));
+        signal
+    }
+
+    #[bench]
+    pub fn bench_solve_4(b: &mut Bencher) {
+        let input = signal(1_000_000, 4);
+        b.iter(|| solve::<4>(&input));
+    }
+
+    #[bench]
+    pub fn bench_solve_14(b: &mut Bencher) {
+        let input = signal(1_000_000, 14);
+        b.iter(|| solve::<14>(&input));
+    }
+
+    #[bench]
+    pub fn bench_solve_26(b: &mut Bencher) {
+        let input = signal(1_000_000, 26);
+        b.iter(|| solve::<26>(&input));
+    }
+
+    #[bench]
+    pub fn bench_find_marker_4(b: &mut Bencher) {
+        let input = signal(1_000_000, 4);
+        b.iter(|| find_marker(input.as_bytes(), 4));
+    }
+
+    #[bench]
+    pub fn bench_find_marker_14(b: &mut Bencher) {
+        let input = signal(1_000_000, 14);
+        b.iter(|| find_marker(input.as_bytes(), 14));
+    }
+
+    #[bench]
+    pub fn bench_find_marker_26(b: &mut Bencher) {
+        let input = signal(1_000_000, 26);
+        b.iter(|| find_marker(input.as_bytes(), 26));
+    }
 }
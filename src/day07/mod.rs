@@ -1,106 +1,1042 @@
 use crate::util::HashMap;
-use std::iter::Peekable;
+use std::fmt::{self, Display, Write};
+use std::fs::{self, Metadata};
+use std::io;
+use std::path::Path;
+
+pub type NodeId = usize;
 
 #[derive(Debug)]
-pub enum DirEntry<'a> {
-    File(File),
-    Dir(Dir<'a>),
+pub enum NodeKind<'a> {
+    File { size: usize },
+    Dir { entries: HashMap<&'a str, NodeId> },
 }
 
 #[derive(Debug)]
-pub struct File {
-    size: usize,
-}
-
-#[derive(Debug, Default)]
-pub struct Dir<'a> {
-    entries: HashMap<&'a str, DirEntry<'a>>,
-}
-
-pub fn parse(input: &str) -> Dir {
-    fn parse_dir<'a>(lines: &mut Peekable<impl Iterator<Item = &'a str>>) -> Dir<'a> {
-        let mut dir = Dir::default();
-
-        while let Some(line) = lines.next() {
-            if let Some(cmd) = line.strip_prefix("$ ") {
-                if cmd == "ls" {
-                } else if let Some(dirname) = cmd.strip_prefix("cd ") {
-                    match dirname {
-                        ".." => return dir,
-                        dirname => {
-                            dir.entries.insert(dirname, DirEntry::Dir(parse_dir(lines)));
-                        }
-                    }
-                } else {
-                    panic!()
-                }
+pub struct Node<'a> {
+    pub name: &'a str,
+    pub parent: Option<NodeId>,
+    pub kind: NodeKind<'a>,
+}
+
+/// A filesystem reconstructed from a shell transcript, stored as an arena of nodes.
+///
+/// Every node is created after its parent, so children always have a greater [NodeId].
+#[derive(Debug)]
+pub struct FileSystem<'a> {
+    nodes: Vec<Node<'a>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParseError {
+    /// A command other than `cd` or `ls`
+    UnknownCommand { line: usize },
+
+    /// Output that doesn't follow an `ls`
+    UnexpectedOutput { line: usize },
+
+    /// A line of `ls` output that isn't `dir <name>` or `<size> <name>`
+    MalformedEntry { line: usize },
+
+    /// A `cd` into something that was listed as a file
+    NotADirectory { line: usize, path: String },
+
+    /// An entry that doesn't match what an earlier `ls` listed
+    Conflict { line: usize, path: String },
+
+    /// A `cd` argument with an empty component, e.g. `a//b`
+    InvalidPath { line: usize, path: String },
+}
+
+impl<'a> FileSystem<'a> {
+    pub const ROOT: NodeId = 0;
+
+    pub fn new() -> Self {
+        FileSystem {
+            nodes: vec![Node {
+                name: "",
+                parent: None,
+                kind: NodeKind::Dir {
+                    entries: HashMap::default(),
+                },
+            }],
+        }
+    }
+
+    pub fn node(&self, id: NodeId) -> &Node<'a> {
+        &self.nodes[id]
+    }
+
+    pub fn node_count(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn is_dir(&self, id: NodeId) -> bool {
+        matches!(self.nodes[id].kind, NodeKind::Dir { .. })
+    }
+
+    /// Iterate over the ids of the entries of a directory, or nothing if `id` is a file
+    pub fn entries(&self, id: NodeId) -> impl Iterator<Item = NodeId> + '_ {
+        let entries = match &self.nodes[id].kind {
+            NodeKind::Dir { entries } => Some(entries.values().copied()),
+            NodeKind::File { .. } => None,
+        };
+        entries.into_iter().flatten()
+    }
+
+    pub fn child(&self, id: NodeId, name: &str) -> Option<NodeId> {
+        match &self.nodes[id].kind {
+            NodeKind::Dir { entries } => entries.get(name).copied(),
+            NodeKind::File { .. } => None,
+        }
+    }
+
+    /// Find a node by its absolute path, e.g. `/a/e/i`
+    pub fn lookup(&self, path: &str) -> Option<NodeId> {
+        let path = path.strip_prefix('/')?;
+        (path.split('/').filter(|name| !name.is_empty()))
+            .try_fold(Self::ROOT, |id, name| self.child(id, name))
+    }
+
+    /// Get the absolute path of a node
+    pub fn path(&self, id: NodeId) -> String {
+        let mut names = vec![];
+        let mut node = &self.nodes[id];
+        while let Some(parent) = node.parent {
+            names.push(node.name);
+            node = &self.nodes[parent];
+        }
+
+        let mut path = String::new();
+        for name in names.iter().rev() {
+            path.push('/');
+            path.push_str(name);
+        }
+        if path.is_empty() {
+            path.push('/');
+        }
+        path
+    }
+
+    /// Get the total size of every node, indexed by [NodeId]
+    pub fn sizes(&self) -> Vec<usize> {
+        let mut sizes: Vec<usize> = (self.nodes.iter())
+            .map(|node| match node.kind {
+                NodeKind::File { size } => size,
+                NodeKind::Dir { .. } => 0,
+            })
+            .collect();
+
+        // children come after their parents, so iterate backwards to sum up sizes bottom-up
+        for id in (1..self.nodes.len()).rev() {
+            let parent = self.nodes[id].parent.unwrap();
+            sizes[parent] += sizes[id];
+        }
+
+        sizes
+    }
+
+    /// Get the total size of every directory
+    pub fn dir_sizes(&self) -> impl Iterator<Item = usize> + '_ {
+        let sizes = self.sizes();
+        (0..self.nodes.len())
+            .filter(|&id| self.is_dir(id))
+            .map(move |id| sizes[id])
+    }
+
+    /// Add an entry to a directory, or check that it matches the existing entry of that name
+    fn insert(
+        &mut self,
+        dir: NodeId,
+        name: &'a str,
+        file_size: Option<usize>,
+        line: usize,
+    ) -> Result<NodeId, ParseError> {
+        if let Some(id) = self.child(dir, name) {
+            return match (&self.nodes[id].kind, file_size) {
+                (NodeKind::Dir { .. }, None) => Ok(id),
+                (&NodeKind::File { size }, Some(new_size)) if size == new_size => Ok(id),
+                _ => Err(ParseError::Conflict {
+                    line,
+                    path: self.path(id),
+                }),
+            };
+        }
+
+        let id = self.nodes.len();
+        self.nodes.push(Node {
+            name,
+            parent: Some(dir),
+            kind: match file_size {
+                Some(size) => NodeKind::File { size },
+                None => NodeKind::Dir {
+                    entries: HashMap::default(),
+                },
+            },
+        });
+        match &mut self.nodes[dir].kind {
+            NodeKind::Dir { entries } => entries.insert(name, id),
+            NodeKind::File { .. } => unreachable!("tried to insert into a file"),
+        };
+
+        Ok(id)
+    }
+
+    /// Resolve the argument of a `cd` from `cwd`, creating any directories along the way
+    fn cd(&mut self, cwd: NodeId, path: &'a str, line: usize) -> Result<NodeId, ParseError> {
+        let invalid = || ParseError::InvalidPath {
+            line,
+            path: path.to_string(),
+        };
+        let (mut dir, relative) = match path.strip_prefix('/') {
+            Some("") => return Ok(Self::ROOT),
+            Some(relative) => (Self::ROOT, relative),
+            None => (cwd, path),
+        };
+
+        // like lookup, allow a trailing slash
+        let relative = relative.strip_suffix('/').unwrap_or(relative);
+        for name in relative.split('/') {
+            dir = match name {
+                "" => return Err(invalid()),
+                "." => dir,
+                ".." => self.nodes[dir].parent.unwrap_or(Self::ROOT),
+                name => self.insert(dir, name, None, line).map_err(|e| match e {
+                    ParseError::Conflict { line, path } => ParseError::NotADirectory { line, path },
+                    e => e,
+                })?,
+            };
+        }
+
+        Ok(dir)
+    }
+}
+
+/// Whether `ls` could list an entry with this name
+fn is_entry_name(name: &str) -> bool {
+    !matches!(name, "" | "." | "..") && !name.contains('/')
+}
+
+impl Default for FileSystem<'_> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+pub fn parse(input: &str) -> Result<FileSystem<'_>, ParseError> {
+    let mut fs = FileSystem::new();
+    let mut cwd = FileSystem::ROOT;
+    let mut listing = false;
+
+    for (line, text) in (1..).zip(input.lines()) {
+        if let Some(cmd) = text.strip_prefix("$ ") {
+            listing = cmd == "ls";
+            if listing {
+                continue;
+            }
+
+            match cmd.strip_prefix("cd ") {
+                Some(path) => cwd = fs.cd(cwd, path, line)?,
+                None => return Err(ParseError::UnknownCommand { line }),
+            }
+        } else if !listing {
+            return Err(ParseError::UnexpectedOutput { line });
+        } else if let Some(dirname) = text.strip_prefix("dir ") {
+            if !is_entry_name(dirname) {
+                return Err(ParseError::MalformedEntry { line });
+            }
+            fs.insert(cwd, dirname, None, line)?;
+        } else {
+            let (size, name) = (text.split_once(' '))
+                .and_then(|(size, name)| Some((size.parse().ok()?, name)))
+                .filter(|&(_, name)| is_entry_name(name))
+                .ok_or(ParseError::MalformedEntry { line })?;
+            fs.insert(cwd, name, Some(size), line)?;
+        }
+    }
+
+    Ok(fs)
+}
+
+pub fn part1(input: &str) -> usize {
+    let fs = parse(input).expect("failed to parse input");
+    fs.dir_sizes().filter(|&s| s <= 100000).sum()
+}
+
+pub fn part2(input: &str) -> usize {
+    let fs = parse(input).expect("failed to parse input");
+    fs.smallest_deletion(Disk::default()).unwrap_or(0)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Disk {
+    pub total_space: usize,
+    pub required_space: usize,
+}
+
+impl Default for Disk {
+    fn default() -> Self {
+        Disk {
+            total_space: 70000000,
+            required_space: 30000000,
+        }
+    }
+}
+
+impl Disk {
+    /// How many bytes need to be freed for `required_space` to be available
+    pub fn to_free(self, used_space: usize) -> usize {
+        let free_space = self.total_space.saturating_sub(used_space);
+        self.required_space.saturating_sub(free_space)
+    }
+}
+
+/// What a [CleanupPlan] should minimise, ties are broken by the other one
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Minimise {
+    #[default]
+    Bytes,
+    Deletions,
+}
+
+/// A path which doesn't exist in the filesystem
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct UnknownPath(pub String);
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CleanupPlan {
+    /// The directories to delete, none of which are inside another
+    pub dirs: Vec<NodeId>,
+    pub freed: usize,
+}
+
+impl FileSystem<'_> {
+    /// Get the size of the smallest directory which would free enough space if deleted
+    pub fn smallest_deletion(&self, disk: Disk) -> Option<usize> {
+        let sizes = self.sizes();
+        let to_free = disk.to_free(sizes[Self::ROOT]);
+        (0..self.nodes.len())
+            .filter(|&id| self.is_dir(id))
+            .map(|id| sizes[id])
+            .filter(|&size| size >= to_free)
+            .min()
+    }
+
+    /// Find the best set of directories to delete to free up enough space. Directories which
+    /// are, or contain, any of the `protected` paths will not be deleted.
+    ///
+    /// Returns an error if a protected path doesn't exist, and None if there is no such set.
+    ///
+    /// This is an exhaustive branch-and-bound search, so while it's fast enough for puzzle inputs
+    /// it's exponential in the worst case.
+    pub fn plan_cleanup(
+        &self,
+        disk: Disk,
+        minimise: Minimise,
+        protected: &[&str],
+    ) -> Result<Option<CleanupPlan>, UnknownPath> {
+        let sizes = self.sizes();
+        let to_free = disk.to_free(sizes[Self::ROOT]);
+
+        let mut deletable = vec![true; self.nodes.len()];
+        for path in protected {
+            let id = self
+                .lookup(path)
+                .ok_or_else(|| UnknownPath(path.to_string()))?;
+            let mut node = Some(id);
+            while let Some(id) = node {
+                deletable[id] = false;
+                node = self.nodes[id].parent;
+            }
+        }
+
+        let mut candidates: Vec<NodeId> = (0..self.nodes.len())
+            .filter(|&id| self.is_dir(id) && deletable[id])
+            .collect();
+        candidates.sort_by_key(|&id| std::cmp::Reverse(sizes[id]));
+
+        // the sum of all candidates after some index, an upper bound of how much they can free
+        let mut remaining: Vec<usize> = candidates
+            .iter()
+            .rev()
+            .scan(0, |sum, &id| {
+                *sum += sizes[id];
+                Some(*sum)
+            })
+            .collect();
+        remaining.reverse();
+        remaining.push(0);
+
+        let mut search = CleanupSearch {
+            fs: self,
+            sizes: &sizes,
+            candidates: &candidates,
+            remaining: &remaining,
+            to_free,
+            minimise,
+            chosen: vec![],
+            best: None,
+        };
+        search.search(0, 0);
+        Ok(search.best)
+    }
+
+    fn is_ancestor(&self, ancestor: NodeId, mut id: NodeId) -> bool {
+        while let Some(parent) = self.nodes[id].parent {
+            if parent == ancestor {
+                return true;
+            }
+            id = parent;
+        }
+        false
+    }
+}
+
+struct CleanupSearch<'a> {
+    fs: &'a FileSystem<'a>,
+    sizes: &'a [usize],
+    candidates: &'a [NodeId],
+    remaining: &'a [usize],
+    to_free: usize,
+    minimise: Minimise,
+    chosen: Vec<NodeId>,
+    best: Option<CleanupPlan>,
+}
+
+impl CleanupSearch<'_> {
+    fn cost(&self, deletions: usize, freed: usize) -> (usize, usize) {
+        match self.minimise {
+            Minimise::Bytes => (freed, deletions),
+            Minimise::Deletions => (deletions, freed),
+        }
+    }
+
+    fn search(&mut self, i: usize, freed: usize) {
+        if freed >= self.to_free {
+            let cost = self.cost(self.chosen.len(), freed);
+            if self
+                .best
+                .as_ref()
+                .is_none_or(|best| cost < self.cost(best.dirs.len(), best.freed))
+            {
+                self.best = Some(CleanupPlan {
+                    dirs: self.chosen.clone(),
+                    freed,
+                });
+            }
+            return;
+        }
+
+        if freed + self.remaining[i] < self.to_free {
+            return;
+        }
+
+        // any plan found from here will delete at least one more dir and free at least `to_free`
+        if let Some(best) = &self.best {
+            let lower_bound = self.cost(self.chosen.len() + 1, self.to_free);
+            if lower_bound >= self.cost(best.dirs.len(), best.freed) {
+                return;
+            }
+        }
+
+        let id = self.candidates[i];
+        let overlaps = (self.chosen.iter())
+            .any(|&other| self.fs.is_ancestor(other, id) || self.fs.is_ancestor(id, other));
+        if !overlaps {
+            self.chosen.push(id);
+            self.search(i + 1, freed + self.sizes[id]);
+            self.chosen.pop();
+        }
+
+        self.search(i + 1, freed);
+    }
+}
+
+/// Queries over a parsed filesystem, in the style of `tree`, `du` and `find`
+#[derive(Debug, Clone, PartialEq, Eq, clap::Parser)]
+#[command(name = "day07")]
+pub enum Query {
+    /// Print the directory tree with the size of every entry
+    Tree,
+
+    /// List the size of every directory, largest first
+    Du {
+        /// Only list the largest N directories
+        #[arg(short = 'n', long)]
+        limit: Option<usize>,
+    },
+
+    /// Find entries by name or size
+    Find(Filter),
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, clap::Args)]
+pub struct Filter {
+    /// Glob pattern to match against the entry name, supporting `*` and `?`
+    #[arg(long)]
+    pub name: Option<String>,
+
+    #[arg(long)]
+    pub min_size: Option<usize>,
+
+    #[arg(long)]
+    pub max_size: Option<usize>,
+
+    /// Only match directories
+    #[arg(long, conflicts_with = "files")]
+    pub dirs: bool,
+
+    /// Only match files
+    #[arg(long)]
+    pub files: bool,
+}
+
+impl FileSystem<'_> {
+    /// Get the absolute path and total size of every node
+    pub fn path_sizes(&self) -> Vec<(String, usize)> {
+        let sizes = self.sizes();
+        (0..self.nodes.len())
+            .map(|id| (self.path(id), sizes[id]))
+            .collect()
+    }
+
+    /// Render the filesystem like the puzzle description does, with entries sorted by name
+    pub fn render_tree(&self) -> String {
+        fn render(fs: &FileSystem, sizes: &[usize], id: NodeId, depth: usize, out: &mut String) {
+            let node = fs.node(id);
+            let name = if id == FileSystem::ROOT {
+                "/"
             } else {
-                if let Some(dirname) = line.strip_prefix("dir ") {
-                    dir.entries.insert(dirname, DirEntry::Dir(Dir::default()));
-                } else {
-                    let (size, name) = line.split_once(' ').unwrap();
-                    let size = size.parse().unwrap();
-                    dir.entries.insert(name, DirEntry::File(File { size }));
+                node.name
+            };
+            let kind = if fs.is_dir(id) { "dir" } else { "file" };
+            let indent = depth * 2;
+            writeln!(out, "{:indent$}- {name} ({kind}, size={})", "", sizes[id]).unwrap();
+
+            let mut entries: Vec<_> = fs.entries(id).collect();
+            entries.sort_by_key(|&entry| fs.node(entry).name);
+            for entry in entries {
+                render(fs, sizes, entry, depth + 1, out);
+            }
+        }
+
+        let mut out = String::new();
+        render(self, &self.sizes(), Self::ROOT, 0, &mut out);
+        out
+    }
+
+    /// Get the path and size of every directory, largest first
+    pub fn du(&self) -> Vec<(String, usize)> {
+        let sizes = self.sizes();
+        let mut dirs: Vec<_> = (0..self.nodes.len())
+            .filter(|&id| self.is_dir(id))
+            .map(|id| (self.path(id), sizes[id]))
+            .collect();
+        dirs.sort_by(|(path1, size1), (path2, size2)| size2.cmp(size1).then(path1.cmp(path2)));
+        dirs
+    }
+
+    /// Find every node matching the filter, in no particular order
+    pub fn find(&self, filter: &Filter) -> Vec<NodeId> {
+        let sizes = self.sizes();
+        (0..self.nodes.len())
+            .filter(|&id| match (filter.dirs, filter.files) {
+                (true, _) => self.is_dir(id),
+                (_, true) => !self.is_dir(id),
+                _ => true,
+            })
+            .filter(|&id| filter.min_size.is_none_or(|min| sizes[id] >= min))
+            .filter(|&id| filter.max_size.is_none_or(|max| sizes[id] <= max))
+            .filter(|&id| match &filter.name {
+                Some(pattern) => glob_match(pattern.as_bytes(), self.nodes[id].name.as_bytes()),
+                None => true,
+            })
+            .collect()
+    }
+
+    /// Run a query, returning its output as text
+    pub fn query(&self, query: &Query) -> String {
+        let mut out = String::new();
+        match query {
+            Query::Tree => out = self.render_tree(),
+            Query::Du { limit } => {
+                for (path, size) in self.du().into_iter().take(limit.unwrap_or(usize::MAX)) {
+                    writeln!(out, "{size}\t{path}").unwrap();
+                }
+            }
+            Query::Find(filter) => {
+                let mut paths: Vec<_> = self
+                    .find(filter)
+                    .into_iter()
+                    .map(|id| self.path(id))
+                    .collect();
+                paths.sort();
+                for path in paths {
+                    writeln!(out, "{path}").unwrap();
                 }
             }
         }
+        out
+    }
+}
 
-        dir
+/// Match a name against a glob pattern, where `*` matches any sequence and `?` any single byte
+fn glob_match(pattern: &[u8], name: &[u8]) -> bool {
+    match (pattern, name) {
+        ([], []) => true,
+        ([b'*', rest @ ..], _) => {
+            glob_match(rest, name) || (!name.is_empty() && glob_match(pattern, &name[1..]))
+        }
+        ([b'?', pattern @ ..], [_, name @ ..]) => glob_match(pattern, name),
+        ([p, pattern @ ..], [n, name @ ..]) if p == n => glob_match(pattern, name),
+        _ => false,
     }
+}
 
-    let mut lines = input.lines().peekable();
+/// Walk a directory on disk and generate a transcript of exploring it with `cd` and `ls`, using
+/// file sizes from the metadata. Symlinks are skipped, and names which can't be written on a
+/// single transcript line are an `InvalidData` error.
+pub fn transcript_from_dir(root: &Path) -> io::Result<String> {
+    fn walk(dir: &Path, out: &mut String) -> io::Result<()> {
+        let entries = read_dir_sorted(dir)?;
 
-    assert_eq!(lines.next(), Some("$ cd /"));
-    parse_dir(&mut lines)
+        out.push_str("$ ls\n");
+        for (name, metadata) in &entries {
+            if name.contains(['\n', '\r']) {
+                let msg = format!("file name can't be written to a transcript: {name:?}");
+                return Err(io::Error::new(io::ErrorKind::InvalidData, msg));
+            }
+            if metadata.is_dir() {
+                writeln!(out, "dir {name}").unwrap();
+            } else {
+                writeln!(out, "{} {name}", metadata.len()).unwrap();
+            }
+        }
+
+        for (name, metadata) in &entries {
+            if metadata.is_dir() {
+                writeln!(out, "$ cd {name}").unwrap();
+                walk(&dir.join(name), out)?;
+                out.push_str("$ cd ..\n");
+            }
+        }
+
+        Ok(())
+    }
+
+    let mut out = String::from("$ cd /\n");
+    walk(root, &mut out)?;
+    Ok(out)
 }
 
-pub fn part1(input: &str) -> usize {
-    let fs = parse(input);
-    let mut sum = 0;
-    find_files_with_sizes(&fs, &mut |s| s <= 100000, &mut sum);
-    sum
+/// Walk a directory on disk and get the total size of every directory in it, keyed by their
+/// path relative to `root`, as they would appear in [transcript_from_dir].
+pub fn dir_sizes_on_disk(root: &Path) -> io::Result<HashMap<String, usize>> {
+    fn walk(dir: &Path, path: &str, sizes: &mut HashMap<String, usize>) -> io::Result<usize> {
+        let mut size = 0;
+        for (name, metadata) in read_dir_sorted(dir)? {
+            if metadata.is_dir() {
+                size += walk(&dir.join(&name), &format!("{path}/{name}"), sizes)?;
+            } else {
+                size += metadata.len() as usize;
+            }
+        }
+
+        let path = if path.is_empty() { "/" } else { path };
+        sizes.insert(path.to_string(), size);
+        Ok(size)
+    }
+
+    let mut sizes = HashMap::default();
+    walk(root, "", &mut sizes)?;
+    Ok(sizes)
 }
 
-pub fn part2(input: &str) -> usize {
-    let fs = parse(input);
-    let total_size = 70000000;
-    let mut size_of_every_dir = vec![];
-    let used_space = find_files_with_sizes(
-        &fs,
-        &mut |s| {
-            size_of_every_dir.push(s);
-            false
-        },
-        &mut 0,
-    );
-    let required_space = 30000000 - (total_size - used_space);
-    size_of_every_dir
-        .into_iter()
-        .filter(|&s| s >= required_space)
-        .min()
-        .unwrap_or(0)
-}
-
-fn find_files_with_sizes(dir: &Dir, f: &mut impl FnMut(usize) -> bool, sum: &mut usize) -> usize {
-    let dir_size = dir
-        .entries
-        .values()
-        .map(|entry| match entry {
-            &DirEntry::File(File { size }) => size,
-            DirEntry::Dir(dir) => find_files_with_sizes(dir, f, sum),
-        })
-        .sum();
-
-    if f(dir_size) {
-        *sum += dir_size;
-    }
-
-    dir_size
+/// A directory whose total size differs between the disk and a parsed transcript of it
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SizeMismatch {
+    pub path: String,
+
+    /// The size from walking the disk, or None if the directory is only in the transcript
+    pub on_disk: Option<usize>,
+
+    /// The size from the parsed transcript, or None if the directory is missing from it
+    pub parsed: Option<usize>,
+}
+
+/// Generate a transcript from a directory on disk, and check that parsing it gives every directory
+/// the same total size as walking the disk does. Returns the directories which differ, by path.
+pub fn check_transcript(root: &Path) -> io::Result<Vec<SizeMismatch>> {
+    let transcript = transcript_from_dir(root)?;
+    let mut disk_sizes = dir_sizes_on_disk(root)?;
+    let fs = parse(&transcript).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    let sizes = fs.sizes();
+
+    let mut mismatches = vec![];
+    for id in (0..fs.node_count()).filter(|&id| fs.is_dir(id)) {
+        let path = fs.path(id);
+        let on_disk = disk_sizes.remove(&path);
+        if on_disk != Some(sizes[id]) {
+            mismatches.push(SizeMismatch {
+                path,
+                on_disk,
+                parsed: Some(sizes[id]),
+            });
+        }
+    }
+    mismatches.extend(disk_sizes.into_iter().map(|(path, size)| SizeMismatch {
+        path,
+        on_disk: Some(size),
+        parsed: None,
+    }));
+
+    mismatches.sort_by(|a, b| a.path.cmp(&b.path));
+    Ok(mismatches)
+}
+
+fn read_dir_sorted(dir: &Path) -> io::Result<Vec<(String, Metadata)>> {
+    let mut entries = vec![];
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let metadata = entry.metadata()?;
+        if metadata.is_symlink() {
+            continue;
+        }
+
+        let name = entry.file_name().into_string().map_err(|name| {
+            let msg = format!("file name is not valid UTF-8: {name:?}");
+            io::Error::new(io::ErrorKind::InvalidData, msg)
+        })?;
+        entries.push((name, metadata));
+    }
+
+    entries.sort_by(|(name1, _), (name2, _)| name1.cmp(name2));
+    Ok(entries)
 }
 
+impl Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseError::UnknownCommand { line } => write!(f, "line {line}: unknown command"),
+            ParseError::UnexpectedOutput { line } => {
+                write!(f, "line {line}: output doesn't follow an ls")
+            }
+            ParseError::MalformedEntry { line } => write!(f, "line {line}: malformed ls output"),
+            ParseError::NotADirectory { line, path } => {
+                write!(f, "line {line}: {path} is not a directory")
+            }
+            ParseError::Conflict { line, path } => {
+                write!(f, "line {line}: {path} doesn't match earlier ls output")
+            }
+            ParseError::InvalidPath { line, path } => {
+                write!(f, "line {line}: invalid path {path:?}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+impl Display for UnknownPath {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "no such file or directory: {}", self.0)
+    }
+}
+
+impl std::error::Error for UnknownPath {}
+
 #[cfg(test)]
 mod tests {
-    use super::{part1, part2};
+    use super::{
+        check_transcript, dir_sizes_on_disk, glob_match, parse, part1, part2, transcript_from_dir,
+        Disk, FileSystem, Filter, Minimise, ParseError, Query, UnknownPath,
+    };
+    use clap::Parser;
+    use std::{fs, io};
+
+    #[test]
+    pub fn test_parse() {
+        let input = include_str!("test-input");
+        let fs = parse(input).unwrap();
+        let sizes = fs.sizes();
+
+        let e = fs.lookup("/a/e").unwrap();
+        assert_eq!(fs.path(e), "/a/e");
+        assert_eq!(sizes[e], 584);
+        assert_eq!(sizes[fs.lookup("/a/e/i").unwrap()], 584);
+        assert_eq!(sizes[fs.lookup("/a").unwrap()], 94853);
+        assert_eq!(sizes[fs.lookup("/d/").unwrap()], 24933642);
+        assert_eq!(sizes[FileSystem::ROOT], 48381165);
+        assert_eq!(fs.lookup("/"), Some(FileSystem::ROOT));
+        assert_eq!(fs.path(FileSystem::ROOT), "/");
+        assert_eq!(fs.lookup("/a/x"), None);
+        assert_eq!(fs.lookup("/b.txt/x"), None);
+    }
+
+    #[test]
+    pub fn test_parse_navigation() {
+        let input = "$ cd ..\n\
+                     $ ls\n\
+                     dir a\n\
+                     10 b\n\
+                     $ cd a\n\
+                     $ ls\n\
+                     20 c\n\
+                     $ cd /\n\
+                     $ ls\n\
+                     dir a\n\
+                     10 b\n\
+                     $ cd a\n\
+                     $ ls\n\
+                     30 d\n";
+        let fs = parse(input).unwrap();
+        let sizes = fs.sizes();
+        assert_eq!(fs.node_count(), 5);
+        assert_eq!(sizes[FileSystem::ROOT], 60);
+        assert_eq!(sizes[fs.lookup("/a").unwrap()], 50);
+        assert_eq!(fs.lookup("/a/c"), Some(3));
+
+        // cd arguments are paths, which may be absolute and contain . and ..
+        let input = "$ cd a/b\n\
+                     $ ls\n\
+                     10 c\n\
+                     $ cd /d/./e/../f/\n\
+                     $ ls\n\
+                     20 g\n\
+                     $ cd ../../a\n\
+                     $ cd .\n\
+                     $ ls\n\
+                     dir b\n\
+                     30 h\n";
+        let fs = parse(input).unwrap();
+        let sizes = fs.sizes();
+        assert_eq!(sizes[FileSystem::ROOT], 60);
+        assert_eq!(sizes[fs.lookup("/a").unwrap()], 40);
+        assert!(fs.lookup("/a/b/c").is_some());
+        assert!(fs.lookup("/d/e").is_some());
+        assert!(fs.lookup("/d/f/g").is_some());
+        assert_eq!(fs.node_count(), 9);
+    }
+
+    #[test]
+    pub fn test_parse_errors() {
+        let error = |input| parse(input).unwrap_err();
+        assert_eq!(error("$ rm -rf /"), ParseError::UnknownCommand { line: 1 });
+        assert_eq!(
+            error("$ cd /\n1 a"),
+            ParseError::UnexpectedOutput { line: 2 }
+        );
+        assert_eq!(error("$ ls\nabc a"), ParseError::MalformedEntry { line: 2 });
+        assert_eq!(
+            error("$ ls\n1 a\n$ cd a"),
+            ParseError::NotADirectory {
+                line: 3,
+                path: "/a".into()
+            }
+        );
+        assert_eq!(
+            error("$ ls\n1 a\n$ ls\n2 a"),
+            ParseError::Conflict {
+                line: 4,
+                path: "/a".into()
+            }
+        );
+        for path in ["", "a//b", "//", "a/b//"] {
+            let input = format!("$ cd {path}\n");
+            let invalid = ParseError::InvalidPath {
+                line: 1,
+                path: path.into(),
+            };
+            assert_eq!(parse(&input).unwrap_err(), invalid);
+        }
+        for entry in ["dir a/b", "dir .", "dir ", "10 ..", "10 a/b", "10 "] {
+            let input = format!("$ ls\n{entry}\n");
+            let malformed = ParseError::MalformedEntry { line: 2 };
+            assert_eq!(parse(&input).unwrap_err(), malformed);
+        }
+    }
+
+    #[test]
+    pub fn test_tree() {
+        let fs = parse(include_str!("test-input")).unwrap();
+        assert_eq!(
+            fs.render_tree(),
+            "- / (dir, size=48381165)\n\
+             \x20 - a (dir, size=94853)\n\
+             \x20   - e (dir, size=584)\n\
+             \x20     - i (file, size=584)\n\
+             \x20   - f (file, size=29116)\n\
+             \x20   - g (file, size=2557)\n\
+             \x20   - h.lst (file, size=62596)\n\
+             \x20 - b.txt (file, size=14848514)\n\
+             \x20 - c.dat (file, size=8504156)\n\
+             \x20 - d (dir, size=24933642)\n\
+             \x20   - d.ext (file, size=5626152)\n\
+             \x20   - d.log (file, size=8033020)\n\
+             \x20   - j (file, size=4060174)\n\
+             \x20   - k (file, size=7214296)\n"
+        );
+    }
+
+    #[test]
+    pub fn test_queries() {
+        let fs = parse(include_str!("test-input")).unwrap();
+        let query = |args: &[&str]| fs.query(&Query::parse_from([&["day07"], args].concat()));
+
+        assert_eq!(
+            query(&["du"]),
+            "48381165\t/\n24933642\t/d\n94853\t/a\n584\t/a/e\n"
+        );
+        assert_eq!(query(&["du", "-n", "1"]), "48381165\t/\n");
+        assert_eq!(
+            query(&["find", "--name", "*.*"]),
+            "/a/h.lst\n/b.txt\n/c.dat\n/d/d.ext\n/d/d.log\n"
+        );
+        assert_eq!(
+            query(&["find", "--dirs", "--max-size", "100000"]),
+            "/a\n/a/e\n"
+        );
+        assert_eq!(
+            query(&["find", "--files", "--name", "?", "--min-size", "5000000"]),
+            "/d/k\n"
+        );
+
+        let filter = Filter {
+            name: Some("d*".into()),
+            ..Filter::default()
+        };
+        assert_eq!(fs.find(&filter).len(), 3);
+
+        assert!(glob_match(b"*.l?g", b"d.log"));
+        assert!(!glob_match(b"*.l?g", b"d.logs"));
+        assert!(Query::try_parse_from(["day07", "find", "--dirs", "--files"]).is_err());
+    }
+
+    #[test]
+    pub fn test_plan_cleanup() {
+        let fs = parse(include_str!("test-input")).unwrap();
+        let d = fs.lookup("/d").unwrap();
+        let plan = |disk, minimise, protected: &[&str]| {
+            let plan = fs.plan_cleanup(disk, minimise, protected).unwrap()?;
+            Some((plan.dirs, plan.freed))
+        };
+
+        let disk = Disk::default();
+        assert_eq!(disk.to_free(48381165), 8381165);
+        assert_eq!(plan(disk, Minimise::Bytes, &[]), Some((vec![d], 24933642)));
+        assert_eq!(plan(disk, Minimise::Bytes, &["/d/k"]), None);
+
+        let disk = Disk {
+            total_space: 48381165,
+            required_space: 24933642 + 500,
+        };
+        let e = fs.lookup("/a/e").unwrap();
+        assert_eq!(
+            plan(disk, Minimise::Bytes, &["/b.txt"]),
+            Some((vec![d, e], 24934226))
+        );
+        assert_eq!(plan(disk, Minimise::Bytes, &["/a/e/i"]), None);
+
+        let disk = Disk {
+            total_space: 100000000,
+            required_space: 1,
+        };
+        assert_eq!(plan(disk, Minimise::Deletions, &[]), Some((vec![], 0)));
+
+        // typos in protected paths are errors, rather than leaving the directory unprotected
+        assert_eq!(
+            fs.plan_cleanup(disk, Minimise::Bytes, &["/d", "/a/ee"]),
+            Err(UnknownPath("/a/ee".to_string()))
+        );
+    }
+
+    #[test]
+    pub fn test_plan_cleanup_minimise() {
+        let input = "$ ls\n\
+                     dir x\n\
+                     dir y\n\
+                     dir z\n\
+                     $ cd x\n\
+                     $ ls\n\
+                     50 a\n\
+                     $ cd /\n\
+                     $ cd y\n\
+                     $ ls\n\
+                     60 a\n\
+                     $ cd /\n\
+                     $ cd z\n\
+                     $ ls\n\
+                     200 a\n";
+        let fs = parse(input).unwrap();
+        let disk = Disk {
+            total_space: 310,
+            required_space: 100,
+        };
+        let path = |id| fs.path(id);
+
+        let plan = fs
+            .plan_cleanup(disk, Minimise::Bytes, &[])
+            .unwrap()
+            .unwrap();
+        assert_eq!(plan.freed, 110);
+        let mut dirs: Vec<_> = plan.dirs.into_iter().map(path).collect();
+        dirs.sort();
+        assert_eq!(dirs, ["/x", "/y"]);
+
+        let plan = fs
+            .plan_cleanup(disk, Minimise::Deletions, &[])
+            .unwrap()
+            .unwrap();
+        assert_eq!(plan.freed, 200);
+        assert_eq!(plan.dirs.into_iter().map(path).collect::<Vec<_>>(), ["/z"]);
+    }
+
+    #[test]
+    pub fn test_transcript_from_dir() {
+        let root = std::env::temp_dir().join(format!("aoc2022-day07-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        for (path, size) in [
+            ("a/e/i", 584),
+            ("a/f", 29116),
+            ("a/h.lst", 62596),
+            ("b.txt", 14848),
+            ("d/with space", 4060),
+            ("d/k", 7214),
+            ("empty/", 0),
+        ] {
+            let path = root.join(path);
+            if size == 0 {
+                fs::create_dir_all(&path).unwrap();
+            } else {
+                fs::create_dir_all(path.parent().unwrap()).unwrap();
+                fs::write(&path, vec![b'x'; size]).unwrap();
+            }
+        }
+
+        let transcript = transcript_from_dir(&root);
+        let disk_sizes = dir_sizes_on_disk(&root);
+        let mismatches = check_transcript(&root);
+        fs::remove_dir_all(&root).unwrap();
+        let (transcript, disk_sizes) = (transcript.unwrap(), disk_sizes.unwrap());
+        assert_eq!(mismatches.unwrap(), []);
+
+        let fs = parse(&transcript).unwrap();
+        let sizes = fs.sizes();
+        assert_eq!(disk_sizes["/"], 584 + 29116 + 62596 + 14848 + 4060 + 7214);
+        assert_eq!(disk_sizes.len(), fs.dir_sizes().count());
+        for (path, size) in disk_sizes {
+            assert_eq!(sizes[fs.lookup(&path).unwrap()], size, "size of {path}");
+        }
+        assert_eq!(sizes[fs.lookup("/d/with space").unwrap()], 4060);
+        assert!(fs.is_dir(fs.lookup("/empty").unwrap()));
+
+        let path = root.join("a").join("new\nline");
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, "x").unwrap();
+        let transcript = transcript_from_dir(&root);
+        fs::remove_dir_all(&root).unwrap();
+        assert_eq!(transcript.unwrap_err().kind(), io::ErrorKind::InvalidData);
+    }
 
     #[test]
     pub fn test_part1() {
@@ -1,3 +1,6 @@
+use crate::util::{encode_pgm, encode_png};
+use std::fmt::Write;
+
 pub fn parse(input: &str) -> Vec<Vec<i8>> {
     input
         .as_bytes()
@@ -13,105 +16,213 @@ pub fn parse(input: &str) -> Vec<Vec<i8>> {
 
 pub fn part1(input: &str) -> usize {
     let trees = parse(input);
+    let vismap = visibility_map(&trees);
+    vismap.iter().flatten().filter(|&&visible| visible).count()
+}
+
+pub fn part2(input: &str) -> usize {
+    let trees = parse(input);
+    let scenicmap = scenic_map(&trees);
+    scenicmap.into_iter().flatten().max().unwrap_or(0)
+}
+
+/// A direction to look in, as a `(row, column)` step
+pub type Ray = (isize, isize);
+
+pub const FOUR_DIRECTIONS: [Ray; 4] = [(-1, 0), (1, 0), (0, -1), (0, 1)];
+
+pub const EIGHT_DIRECTIONS: [Ray; 8] = [
+    (-1, 0),
+    (-1, 1),
+    (0, 1),
+    (1, 1),
+    (1, 0),
+    (1, -1),
+    (0, -1),
+    (-1, -1),
+];
+
+/// Every line of trees in the forest which look along `ray`. Each line starts at the edge the
+/// ray points towards, and steps backwards along the ray, so that every tree is in exactly one
+/// line.
+fn sightlines(rows: usize, cols: usize, ray: Ray) -> impl Iterator<Item = Vec<(usize, usize)>> {
+    assert_ne!(ray, (0, 0), "can't look along a zero-length ray");
+
+    let step = move |(i, j): (usize, usize), (di, dj): Ray| {
+        let (i, j) = (i.checked_add_signed(di)?, j.checked_add_signed(dj)?);
+        (i < rows && j < cols).then_some((i, j))
+    };
+    let reverse = (-ray.0, -ray.1);
+
+    (0..rows)
+        .flat_map(move |i| (0..cols).map(move |j| (i, j)))
+        .filter(move |&pos| step(pos, ray).is_none())
+        .map(move |start| std::iter::successors(Some(start), |&pos| step(pos, reverse)).collect())
+}
+
+/// Find which trees are visible from outside the forest, looking along the four axes
+pub fn visibility_map(trees: &[Vec<i8>]) -> Vec<Vec<bool>> {
+    visibility_map_along(trees, &FOUR_DIRECTIONS)
+}
+
+/// Find which trees are visible from outside the forest, looking along any of the `rays`
+pub fn visibility_map_along(trees: &[Vec<i8>], rays: &[Ray]) -> Vec<Vec<bool>> {
     let rows = trees.len();
-    let cols = trees[0].len();
+    let cols = trees.first().map(Vec::len).unwrap_or(0);
     let mut vismap = vec![vec![false; cols]; rows];
 
-    for i in 0..rows {
-        let mut tallest_tree = -1;
-        for j in 0..cols {
-            if trees[i][j] > tallest_tree {
-                tallest_tree = trees[i][j];
-                vismap[i][j] = true;
-            }
-        }
-    }
-    for i in 0..rows {
-        let mut tallest_tree = -1;
-        for j in (0..cols).rev() {
-            if trees[i][j] > tallest_tree {
-                tallest_tree = trees[i][j];
-                vismap[i][j] = true;
-            }
-        }
-    }
-    for j in 0..cols {
+    for line in rays.iter().flat_map(|&ray| sightlines(rows, cols, ray)) {
         let mut tallest_tree = -1;
-        for i in 0..rows {
+        for (i, j) in line {
             if trees[i][j] > tallest_tree {
                 tallest_tree = trees[i][j];
                 vismap[i][j] = true;
             }
         }
     }
-    for j in 0..cols {
-        let mut tallest_tree = -1;
-        for i in (0..rows).rev() {
-            if trees[i][j] > tallest_tree {
-                tallest_tree = trees[i][j];
-                vismap[i][j] = true;
-            }
+
+    vismap
+}
+
+/// Compute the scenic score of every tree, looking along the four axes
+pub fn scenic_map(trees: &[Vec<i8>]) -> Vec<Vec<usize>> {
+    scenic_map_along(trees, &FOUR_DIRECTIONS)
+}
+
+/// Compute the scenic score of every tree, as the product of its viewing distances along `rays`
+pub fn scenic_map_along(trees: &[Vec<i8>], rays: &[Ray]) -> Vec<Vec<usize>> {
+    let rows = trees.len();
+    let cols = trees.first().map(Vec::len).unwrap_or(0);
+    let mut scenicmap = vec![vec![1; cols]; rows];
+
+    for &ray in rays {
+        let distances = viewing_distances(trees, ray);
+        for (score, distance) in scenicmap
+            .iter_mut()
+            .flatten()
+            .zip(distances.iter().flatten())
+        {
+            *score *= distance;
         }
     }
 
-    vismap.iter().flatten().filter(|&&visible| visible).count()
+    scenicmap
 }
 
-pub fn part2(input: &str) -> usize {
-    let trees = parse(input);
+/// Compute how many trees every tree can see along `ray` before its view is blocked
+pub fn viewing_distances(trees: &[Vec<i8>], ray: Ray) -> Vec<Vec<usize>> {
     let rows = trees.len();
-    let cols = trees[0].len();
-    let mut scenicmap = vec![vec![0; cols]; rows];
-
-    for i in 0..rows {
-        for j in 0..cols {
-            let mut scenicness = 1;
-            let mut count = 0;
-            for i2 in (i + 1)..rows {
-                count += 1;
-                if trees[i2][j] >= trees[i][j] {
-                    break;
-                }
-            }
-            scenicness *= count;
+    let cols = trees.first().map(Vec::len).unwrap_or(0);
+    let mut distances = vec![vec![0; cols]; rows];
 
-            let mut count = 0;
-            for i2 in (0..i).rev() {
-                count += 1;
-                if trees[i2][j] >= trees[i][j] {
-                    break;
-                }
-            }
-            scenicness *= count;
+    // indices of the trees along the line which might block the view of trees further along,
+    // in order of non-increasing height, since a tree of equal height blocks the view too
+    let mut blocking: Vec<usize> = Vec::new();
 
-            let mut count = 0;
-            for j2 in (j + 1)..rows {
-                count += 1;
-                if trees[i][j2] >= trees[i][j] {
+    // walking along a line, find the viewing distance of every tree looking back towards the edge
+    for line in sightlines(rows, cols, ray) {
+        blocking.clear();
+        for (k, &(i, j)) in line.iter().enumerate() {
+            while let Some(&b) = blocking.last() {
+                let (bi, bj) = line[b];
+                if trees[bi][bj] >= trees[i][j] {
                     break;
                 }
+                blocking.pop();
             }
-            scenicness *= count;
 
-            let mut count = 0;
-            for j2 in (0..j).rev() {
-                count += 1;
-                if trees[i][j2] >= trees[i][j] {
-                    break;
-                }
-            }
-            scenicness *= count;
+            distances[i][j] = match blocking.last() {
+                Some(&b) => k - b,
+                None => k,
+            };
+            blocking.push(k);
+        }
+    }
+
+    distances
+}
+
+/// Convert a visibility map into a heatmap, where visible trees have the value 1
+pub fn visibility_heatmap(vismap: &[Vec<bool>]) -> Vec<Vec<usize>> {
+    (vismap.iter())
+        .map(|row| row.iter().map(|&visible| visible as usize).collect())
+        .collect()
+}
+
+/// Render a heatmap for the terminal using ANSI true colour backgrounds, going from blue for the
+/// lowest values to red for the highest.
+pub fn render_ansi(map: &[Vec<usize>]) -> String {
+    const GRADIENT: [(f64, f64, f64); 5] = [
+        (0.0, 0.0, 64.0),
+        (0.0, 128.0, 255.0),
+        (0.0, 255.0, 0.0),
+        (255.0, 255.0, 0.0),
+        (255.0, 0.0, 0.0),
+    ];
 
-            scenicmap[i][j] = scenicness;
+    let max = map.iter().flatten().copied().max().unwrap_or(0).max(1);
+    let mut out = String::new();
+    for row in map {
+        for &value in row {
+            let t = value as f64 / max as f64 * (GRADIENT.len() - 1) as f64;
+            let i = (t as usize).min(GRADIENT.len() - 2);
+            let (t, from, to) = (t - i as f64, GRADIENT[i], GRADIENT[i + 1]);
+            let lerp = |from: f64, to: f64| (from + (to - from) * t).round() as u8;
+            let (r, g, b) = (lerp(from.0, to.0), lerp(from.1, to.1), lerp(from.2, to.2));
+            write!(out, "\x1b[48;2;{r};{g};{b}m  ").unwrap();
         }
+        out.push_str("\x1b[0m\n");
     }
+    out
+}
 
-    scenicmap.into_iter().flatten().max().unwrap_or(0)
+/// Render a heatmap as a matrix of comma-separated values
+pub fn render_csv(map: &[Vec<usize>]) -> String {
+    let mut out = String::new();
+    for row in map {
+        for (j, value) in row.iter().enumerate() {
+            if j != 0 {
+                out.push(',');
+            }
+            write!(out, "{value}").unwrap();
+        }
+        out.push('\n');
+    }
+    out
+}
+
+/// Scale a heatmap to grayscale pixels, returning its width, height and pixels
+fn grayscale(map: &[Vec<usize>]) -> (usize, usize, Vec<u8>) {
+    let height = map.len();
+    let width = map.first().map(Vec::len).unwrap_or(0);
+    let max = map.iter().flatten().copied().max().unwrap_or(0).max(1);
+    let pixels = (map.iter().flatten())
+        .map(|&value| (value * 255 / max) as u8)
+        .collect();
+    (width, height, pixels)
+}
+
+/// Render a heatmap as a grayscale PGM image, where white is the highest value
+pub fn render_pgm(map: &[Vec<usize>]) -> Vec<u8> {
+    let (width, height, pixels) = grayscale(map);
+    encode_pgm(width, height, &pixels)
+}
+
+/// Render a heatmap as a grayscale PNG image, where white is the highest value
+pub fn render_png(map: &[Vec<usize>]) -> Vec<u8> {
+    let (width, height, pixels) = grayscale(map);
+    encode_png(width, height, &pixels)
 }
 
 #[cfg(test)]
 mod tests {
-    use super::{part1, part2};
+    use super::{
+        parse, part1, part2, render_ansi, render_csv, render_pgm, render_png, scenic_map,
+        scenic_map_along, viewing_distances, visibility_heatmap, visibility_map,
+        visibility_map_along, Ray, EIGHT_DIRECTIONS, FOUR_DIRECTIONS,
+    };
+    use crate::util::Lcg;
+    use test::Bencher;
 
     //#[test]
     //pub fn test_parse() {
@@ -130,4 +241,140 @@ mod tests {
         let input = include_str!("test-input");
         assert_eq!(part2(input), 8);
     }
+
+    #[test]
+    pub fn test_render() {
+        let trees = parse(include_str!("test-input"));
+        let scenicmap = scenic_map(&trees);
+        assert_eq!(
+            render_csv(&scenicmap),
+            "0,0,0,0,0\n\
+             0,1,4,1,0\n\
+             0,6,1,2,0\n\
+             0,1,8,3,0\n\
+             0,0,0,0,0\n"
+        );
+
+        let vismap = visibility_heatmap(&visibility_map(&trees));
+        assert_eq!(
+            render_csv(&vismap),
+            "1,1,1,1,1\n\
+             1,1,1,0,1\n\
+             1,1,0,1,1\n\
+             1,0,1,0,1\n\
+             1,1,1,1,1\n"
+        );
+
+        let ansi = render_ansi(&scenicmap);
+        assert_eq!(ansi.lines().count(), 5);
+        assert_eq!(ansi.matches("\x1b[48;2;").count(), 25);
+        assert!(ansi.contains("\x1b[48;2;0;0;64m"));
+        assert!(ansi.contains("\x1b[48;2;255;0;0m"));
+
+        let pgm = render_pgm(&scenicmap);
+        assert!(pgm.starts_with(b"P5\n5 5\n255\n"));
+        assert_eq!(&pgm[pgm.len() - 10..pgm.len() - 5], [0, 31, 255, 95, 0]);
+
+        let png = render_png(&vismap);
+        assert!(png.starts_with(b"\x89PNG\r\n\x1a\n\0\0\0\x0dIHDR\0\0\0\x05\0\0\0\x05"));
+        assert!(png.ends_with(b"IEND\xae\x42\x60\x82"));
+    }
+
+    #[test]
+    pub fn test_non_square() {
+        let input = "30373\n25512\n65332\n";
+        assert_eq!(part1(input), 14);
+        assert_eq!(part2(input), 2);
+
+        let tall = "326\n055\n353\n713\n322\n";
+        assert_eq!(part1(tall), 14);
+        assert_eq!(part2(tall), 2);
+    }
+
+    #[test]
+    pub fn test_against_brute_force() {
+        for (rows, cols) in [(1, 1), (1, 7), (7, 1), (13, 29), (31, 8)] {
+            let trees = forest(rows, cols);
+            let (vismap, scenicmap) = brute_force(&trees, &FOUR_DIRECTIONS);
+            assert_eq!(visibility_map(&trees), vismap, "{rows}x{cols}");
+            assert_eq!(scenic_map(&trees), scenicmap, "{rows}x{cols}");
+
+            for rays in [&EIGHT_DIRECTIONS[..], &[(1, 2), (-3, 1)], &[(0, -4)]] {
+                let (vismap, scenicmap) = brute_force(&trees, rays);
+                assert_eq!(
+                    visibility_map_along(&trees, rays),
+                    vismap,
+                    "{rows}x{cols} {rays:?}"
+                );
+                assert_eq!(
+                    scenic_map_along(&trees, rays),
+                    scenicmap,
+                    "{rows}x{cols} {rays:?}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    pub fn test_eight_directions() {
+        let trees = parse(include_str!("test-input"));
+        let vismap = visibility_map_along(&trees, &EIGHT_DIRECTIONS);
+        assert_eq!(vismap.iter().flatten().filter(|&&v| v).count(), 22);
+
+        // the 5 in the fourth row sees past a 3 and a 2 to the north-east edge
+        assert_eq!(viewing_distances(&trees, (-1, 1))[3][2], 2);
+        assert_eq!(viewing_distances(&trees, (1, 1))[1][1], 3);
+    }
+
+    /// Walk outwards from every tree along every ray
+    fn brute_force(trees: &[Vec<i8>], rays: &[Ray]) -> (Vec<Vec<bool>>, Vec<Vec<usize>>) {
+        let rows = trees.len() as isize;
+        let cols = trees[0].len() as isize;
+        let mut vismap = vec![vec![false; cols as usize]; rows as usize];
+        let mut scenicmap = vec![vec![1; cols as usize]; rows as usize];
+
+        for i in 0..rows {
+            for j in 0..cols {
+                let height = trees[i as usize][j as usize];
+                for &(di, dj) in rays {
+                    let (mut i2, mut j2) = (i + di, j + dj);
+                    let mut count = 0;
+                    let mut visible = true;
+                    while (0..rows).contains(&i2) && (0..cols).contains(&j2) {
+                        count += 1;
+                        if trees[i2 as usize][j2 as usize] >= height {
+                            visible = false;
+                            break;
+                        }
+                        i2 += di;
+                        j2 += dj;
+                    }
+                    vismap[i as usize][j as usize] |= visible;
+                    scenicmap[i as usize][j as usize] *= count;
+                }
+            }
+        }
+
+        (vismap, scenicmap)
+    }
+
+    /// Generate a pseudo-random forest
+    fn forest(rows: usize, cols: usize) -> Vec<Vec<i8>> {
+        let mut rng = Lcg(0x2022_1208);
+        (0..rows)
+            .map(|_| (0..cols).map(|_| rng.below(10) as i8).collect())
+            .collect()
+    }
+
+    #[bench]
+    pub fn bench_visibility_map(b: &mut Bencher) {
+        let trees = forest(1000, 700);
+        b.iter(|| visibility_map(&trees));
+    }
+
+    #[bench]
+    pub fn bench_scenic_map(b: &mut Bencher) {
+        let trees = forest(1000, 700);
+        b.iter(|| scenic_map(&trees));
+    }
 }
@@ -6,94 +6,322 @@ pub struct Pos {
     y: i32,
 }
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum Dir {
     Up,
     Down,
     Left,
     Right,
+    UpLeft,
+    UpRight,
+    DownLeft,
+    DownRight,
 }
 
-pub fn parse(input: &str) -> Vec<(Dir, i32)> {
-    input
-        .lines()
-        .map(|line| {
-            (
-                match line.as_bytes()[0] {
-                    b'U' => Dir::Up,
-                    b'D' => Dir::Down,
-                    b'L' => Dir::Left,
-                    b'R' => Dir::Right,
-                    c => panic!("unexpected input: {}", c as char),
-                },
-                line[2..].parse().unwrap(),
-            )
+impl Dir {
+    fn parse(s: &str) -> Option<Dir> {
+        Some(match s {
+            "U" => Dir::Up,
+            "D" => Dir::Down,
+            "L" => Dir::Left,
+            "R" => Dir::Right,
+            "UL" => Dir::UpLeft,
+            "UR" => Dir::UpRight,
+            "DL" => Dir::DownLeft,
+            "DR" => Dir::DownRight,
+            _ => return None,
         })
-        .collect()
+    }
+
+    /// The change in (x, y) of moving one step in this direction
+    pub fn offset(&self) -> (i32, i32) {
+        match self {
+            Dir::Up => (0, 1),
+            Dir::Down => (0, -1),
+            Dir::Left => (-1, 0),
+            Dir::Right => (1, 0),
+            Dir::UpLeft => (-1, 1),
+            Dir::UpRight => (1, 1),
+            Dir::DownLeft => (-1, -1),
+            Dir::DownRight => (1, -1),
+        }
+    }
 }
 
-pub fn part1(input: &str) -> usize {
-    simulate_rope::<2>(input)
+fn parse_move(line: &str) -> (Dir, i32) {
+    let (dir, steps) = line
+        .split_once(' ')
+        .unwrap_or_else(|| panic!("unexpected input: {line:?}"));
+    let dir = Dir::parse(dir).unwrap_or_else(|| panic!("unexpected direction: {dir:?}"));
+    (dir, steps.parse().unwrap())
 }
 
-pub fn part2(input: &str) -> usize {
-    simulate_rope::<10>(input)
+pub fn parse(input: &str) -> Vec<(Dir, i32)> {
+    input.lines().map(parse_move).collect()
 }
 
-/// Simulate dragging a rope around by its head, returning the number of unique spots visited by
-/// the tail
-fn simulate_rope<const N: usize>(input: &str) -> usize {
-    let mut visited = HashSet::default();
-    visited.insert(Pos::default());
+/// Several named ropes, and commands for moving them
+pub struct MultiRope<'a> {
+    ropes: Vec<(&'a str, Rope)>,
+
+    /// Moves for the ropes, where `None` means that every rope moves
+    commands: Vec<(Option<Vec<&'a str>>, Dir, i32)>,
+}
 
-    let mut segments = [Pos::default(); N];
+/// Parse ropes and commands for moving them. Ropes are declared like `rope <name> <knots>`,
+/// optionally followed by a starting position like `at 3,-2`. Commands are moves, optionally
+/// prefixed by a comma-separated list of ropes, like `a,b UR 4`. Moves without a prefix move
+/// every rope, so rope names must be unique and can't be directions.
+pub fn parse_multi(input: &str) -> MultiRope<'_> {
+    let mut ropes = vec![];
+    let mut commands = vec![];
 
-    for (dir, steps) in parse(input) {
-        for _ in 0..steps {
-            let head = &mut segments[0];
-            match dir {
-                Dir::Up => head.y += 1,
-                Dir::Down => head.y -= 1,
-                Dir::Left => head.x -= 1,
-                Dir::Right => head.x += 1,
+    for line in input.lines() {
+        if let Some(rope) = line.strip_prefix("rope ") {
+            let mut words = rope.split(' ');
+            let (Some(name), Some(knots)) = (words.next(), words.next()) else {
+                panic!("unexpected rope declaration: {line:?}");
             };
+            assert!(
+                Dir::parse(name).is_none(),
+                "rope named after a direction: {name:?}"
+            );
+            assert!(
+                ropes.iter().all(|(rope, _)| rope != &name),
+                "duplicate rope: {name:?}"
+            );
+            let mut rope = Rope::new(knots.parse().unwrap());
+            if let (Some("at"), Some(start)) = (words.next(), words.next()) {
+                let (x, y) = start.split_once(',').unwrap();
+                rope.knots.fill(Pos {
+                    x: x.parse().unwrap(),
+                    y: y.parse().unwrap(),
+                });
+            }
+            ropes.push((name, rope));
+        } else if line.split(' ').next().and_then(Dir::parse).is_some() {
+            let (dir, steps) = parse_move(line);
+            commands.push((None, dir, steps));
+        } else {
+            let (names, line) = line.split_once(' ').unwrap();
+            let names: Vec<&str> = names.split(',').collect();
+            for name in &names {
+                assert!(
+                    ropes.iter().any(|(rope, _)| rope == name),
+                    "undeclared rope: {name:?}"
+                );
+            }
+            let (dir, steps) = parse_move(line);
+            commands.push((Some(names), dir, steps));
+        }
+    }
 
-            for i in 1..segments.len() {
-                let [a, b] = get_2_mut(&mut segments, i - 1, i);
-                // find in what way `a` has moved relative to `b`, and move `b` accordingly
-                match (a.x - b.x, a.y - b.y) {
-                    (2.., _dy) => {
-                        b.y = a.y;
-                        b.x = a.x - 1;
-                    }
-                    (..=-2, _dy) => {
-                        b.y = a.y;
-                        b.x = a.x + 1;
-                    }
-                    (_dx, 2..) => {
-                        b.y = a.y - 1;
-                        b.x = a.x;
-                    }
-                    (_dx, ..=-2) => {
-                        b.y = a.y + 1;
-                        b.x = a.x;
+    MultiRope { ropes, commands }
+}
+
+impl<'a> MultiRope<'a> {
+    /// Where a rope starts, or None if there's no rope with that name
+    pub fn start(&self, name: &str) -> Option<Pos> {
+        let (_, rope) = self.ropes.iter().find(|(rope, _)| *rope == name)?;
+        Some(rope.tail())
+    }
+
+    /// Run every command, returning the spots visited by the tail of every rope
+    pub fn simulate(&self) -> Vec<(&'a str, HashSet<Pos>)> {
+        let mut ropes = self.ropes.clone();
+        let mut visited: Vec<_> = (ropes.iter())
+            .map(|(name, rope)| (*name, HashSet::from_iter([rope.tail()])))
+            .collect();
+
+        for (names, dir, steps) in &self.commands {
+            for _ in 0..*steps {
+                for ((name, rope), (_, visited)) in ropes.iter_mut().zip(&mut visited) {
+                    if names.as_ref().is_none_or(|names| names.contains(name)) {
+                        rope.step(*dir);
+                        visited.insert(rope.tail());
                     }
-                    _ => {}
                 }
             }
+        }
+
+        visited
+    }
+}
+
+/// Find the spots visited by the tails of at least two different ropes
+pub fn shared_visits(visited: &[(&str, HashSet<Pos>)]) -> HashSet<Pos> {
+    let mut seen = HashSet::default();
+    let mut shared = HashSet::default();
+    for (_, visited) in visited {
+        for &pos in visited {
+            if !seen.insert(pos) {
+                shared.insert(pos);
+            }
+        }
+    }
+    shared
+}
 
-            //let head = *segments.first().unwrap();
-            let tail = *segments.last().unwrap();
-            //println!("H: {head:?} \t\t T: {tail:?}");
-            visited.insert(tail);
+pub fn part1(input: &str) -> usize {
+    simulate_rope(&parse(input), 2)
+}
+
+pub fn part2(input: &str) -> usize {
+    simulate_rope(&parse(input), 10)
+}
+
+/// Simulate dragging a rope with the given number of knots around by its head, returning the
+/// number of unique spots visited by the tail
+pub fn simulate_rope(moves: &[(Dir, i32)], knots: usize) -> usize {
+    let mut rope = Rope::new(knots);
+    let mut visited = HashSet::default();
+    visited.insert(rope.tail());
+
+    for &(dir, steps) in moves {
+        for _ in 0..steps {
+            rope.step(dir);
+            visited.insert(rope.tail());
         }
     }
 
     visited.len()
 }
 
+/// Iterate over the state of the rope after every step of the head
+pub fn trace(moves: &[(Dir, i32)], knots: usize) -> impl Iterator<Item = Rope> + '_ {
+    let mut rope = Rope::new(knots);
+    (moves.iter())
+        .flat_map(|&(dir, steps)| (0..steps).map(move |_| dir))
+        .map(move |dir| {
+            rope.step(dir);
+            rope.clone()
+        })
+}
+
+/// Find the unique spots visited by every knot of the rope
+pub fn visited_per_knot(moves: &[(Dir, i32)], knots: usize) -> Vec<HashSet<Pos>> {
+    let mut visited = vec![HashSet::default(); knots];
+    for set in &mut visited {
+        set.insert(Pos::default());
+    }
+
+    for rope in trace(moves, knots) {
+        for (set, &knot) in visited.iter_mut().zip(rope.knots()) {
+            set.insert(knot);
+        }
+    }
+
+    visited
+}
+
+/// Render the spots visited by a knot like the puzzle does, with `s` marking where it started
+pub fn render_visits(visited: &HashSet<Pos>, start: Pos) -> String {
+    render_grid(start, visited.iter().copied(), |pos| {
+        if pos == start {
+            's'
+        } else if visited.contains(&pos) {
+            '#'
+        } else {
+            '.'
+        }
+    })
+}
+
+/// Render a rope like the puzzle does, with `H` for the head, `T` for the tail of a two-knot
+/// rope, and the index of every other knot. Knots closer to the head cover the ones behind them.
+pub fn render_rope(rope: &Rope) -> String {
+    let knots = rope.knots();
+    render_grid(Pos::default(), knots.iter().copied(), |pos| {
+        match knots.iter().position(|&knot| knot == pos) {
+            Some(0) => 'H',
+            Some(1) if knots.len() == 2 => 'T',
+            Some(i) => char::from_digit(i as u32 % 36, 36).unwrap(),
+            None if pos == Pos::default() => 's',
+            None => '.',
+        }
+    })
+}
+
+/// Render the smallest grid containing the start and all `positions`, with up being positive y
+fn render_grid(
+    start: Pos,
+    positions: impl Iterator<Item = Pos>,
+    cell: impl Fn(Pos) -> char,
+) -> String {
+    let (mut min, mut max) = (start, start);
+    for pos in positions {
+        min = Pos {
+            x: min.x.min(pos.x),
+            y: min.y.min(pos.y),
+        };
+        max = Pos {
+            x: max.x.max(pos.x),
+            y: max.y.max(pos.y),
+        };
+    }
+
+    let mut out = String::new();
+    for y in (min.y..=max.y).rev() {
+        out.extend((min.x..=max.x).map(|x| cell(Pos { x, y })));
+        out.push('\n');
+    }
+    out
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Rope {
+    knots: Vec<Pos>,
+}
+
+impl Rope {
+    /// Create a rope with all knots at the origin
+    pub fn new(knots: usize) -> Self {
+        assert!(knots > 0, "a rope needs at least one knot");
+        Rope {
+            knots: vec![Pos::default(); knots],
+        }
+    }
+
+    pub fn knots(&self) -> &[Pos] {
+        &self.knots
+    }
+
+    pub fn head(&self) -> Pos {
+        self.knots[0]
+    }
+
+    pub fn tail(&self) -> Pos {
+        *self.knots.last().unwrap()
+    }
+
+    /// Move the head one step, and let the rest of the knots follow
+    pub fn step(&mut self, dir: Dir) {
+        let head = &mut self.knots[0];
+        let (dx, dy) = dir.offset();
+        head.x += dx;
+        head.y += dy;
+
+        for i in 1..self.knots.len() {
+            let [a, b] = get_2_mut(&mut self.knots, i - 1, i);
+            // if `a` is no longer touching `b`, move `b` one step towards it, diagonally if needed
+            let (dx, dy) = (a.x - b.x, a.y - b.y);
+            if dx.abs() <= 1 && dy.abs() <= 1 {
+                // the rest of the rope won't move either
+                break;
+            }
+            b.x += dx.signum();
+            b.y += dy.signum();
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    use super::{part1, part2};
+    use super::{
+        parse, parse_multi, part1, part2, render_rope, render_visits, shared_visits, trace,
+        visited_per_knot, Dir, Pos, Rope,
+    };
+    use crate::util::Lcg;
 
     #[test]
     pub fn test_part1() {
@@ -109,4 +337,140 @@ mod tests {
         let input = include_str!("test-input2");
         assert_eq!(part2(input), 36);
     }
+
+    #[test]
+    pub fn test_trace() {
+        let moves = parse(include_str!("test-input1"));
+        let ropes: Vec<_> = trace(&moves, 10).collect();
+        assert_eq!(ropes.len(), 24);
+        assert_eq!(render_rope(&ropes[3]), "4321H\n");
+        assert_eq!(
+            render_rope(&ropes[7]),
+            "....H\n\
+             ....1\n\
+             ..432\n\
+             .5...\n\
+             6....\n"
+        );
+
+        let ropes: Vec<_> = trace(&moves, 2).collect();
+        assert_eq!(render_rope(&ropes[0]), "TH\n");
+        assert_eq!(render_rope(ropes.last().unwrap()), ".TH\n...\ns..\n");
+    }
+
+    #[test]
+    pub fn test_visits() {
+        let moves = parse(include_str!("test-input1"));
+        let visited = visited_per_knot(&moves, 2);
+        assert_eq!(visited[1].len(), 13);
+        assert_eq!(
+            render_visits(&visited[1], Pos::default()),
+            "..##.\n\
+             ...##\n\
+             .####\n\
+             ....#\n\
+             s###.\n"
+        );
+
+        let moves = parse(include_str!("test-input2"));
+        let visited = visited_per_knot(&moves, 10);
+        assert_eq!(visited[9].len(), 36);
+
+        let moves = parse(include_str!("test-input1"));
+        let visited = visited_per_knot(&moves, 10);
+        assert_eq!(visited[9].len(), 1);
+        assert_eq!(render_visits(&visited[9], Pos::default()), "s\n");
+    }
+
+    #[test]
+    pub fn test_diagonal_moves() {
+        let moves = parse("UR 3\nDL 1\nR 1\n");
+        assert_eq!(moves[0], (Dir::UpRight, 3));
+        let visited = visited_per_knot(&moves, 3);
+        assert_eq!(
+            render_visits(&visited[0], Pos::default()),
+            "...#\n..##\n.#..\ns...\n"
+        );
+        assert_eq!(render_visits(&visited[2], Pos::default()), ".#\ns.\n");
+    }
+
+    #[test]
+    pub fn test_multi_rope() {
+        let input = "rope short 2\n\
+                     rope long 10 at 2,0\n\
+                     R 4\n\
+                     short U 2\n\
+                     long,short DL 3\n";
+        let ropes = parse_multi(input);
+        let visited = ropes.simulate();
+        let render =
+            |(name, visited): &(&str, _)| render_visits(visited, ropes.start(name).unwrap());
+        assert_eq!(visited[0].0, "short");
+        assert_eq!(
+            render(&visited[0]),
+            "....#\n\
+             s###.\n\
+             ..#..\n"
+        );
+
+        // the long rope only gets stretched out, so its tail never moves
+        assert_eq!(visited[1].0, "long");
+        assert_eq!(ropes.start("long"), Some(Pos { x: 2, y: 0 }));
+        assert_eq!(render(&visited[1]), "s\n");
+
+        assert_eq!(
+            shared_visits(&visited),
+            [Pos { x: 2, y: 0 }].into_iter().collect()
+        );
+
+        let ropes = parse_multi("rope A 2\nrope Up 2\nA U 1\nUp,A R 2\n");
+        let visited = ropes.simulate();
+        assert_eq!(render_visits(&visited[0].1, Pos::default()), ".#\ns.\n");
+        assert_eq!(render_visits(&visited[1].1, Pos::default()), "s#\n");
+    }
+
+    #[test]
+    #[should_panic(expected = "duplicate rope")]
+    pub fn test_multi_rope_duplicate() {
+        parse_multi("rope a 2\nrope a 3\n");
+    }
+
+    #[test]
+    #[should_panic(expected = "rope named after a direction")]
+    pub fn test_multi_rope_direction_name() {
+        parse_multi("rope UL 2\n");
+    }
+
+    #[test]
+    pub fn test_diagonal_follow() {
+        // drag the head up and to the right so that the second knot moves diagonally, which
+        // leaves the third knot two steps away on both axes
+        let mut rope = Rope::new(3);
+        for dir in [Dir::Right, Dir::Up, Dir::Up, Dir::Right, Dir::Right] {
+            rope.step(dir);
+        }
+        assert_eq!(
+            rope.knots(),
+            [Pos { x: 3, y: 2 }, Pos { x: 2, y: 2 }, Pos { x: 1, y: 1 }]
+        );
+    }
+
+    #[test]
+    pub fn test_knots_stay_touching() {
+        let dirs = [Dir::Up, Dir::Down, Dir::Left, Dir::Right];
+        let mut rng = Lcg(0x2022_1209);
+
+        for knots in [1, 2, 3, 10, 30] {
+            let mut rope = Rope::new(knots);
+            for _ in 0..2000 {
+                let dir = dirs[rng.below(4) as usize];
+                for _ in 0..=rng.below(12) {
+                    rope.step(dir);
+                    for [a, b] in rope.knots().array_windows() {
+                        assert!((a.x - b.x).abs() <= 1 && (a.y - b.y).abs() <= 1, "{rope:?}");
+                    }
+                }
+            }
+        }
+    }
 }
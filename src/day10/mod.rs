@@ -1,112 +1,780 @@
-pub enum Instruction {
-    NoOp,
-    AddX(i32),
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt::{self, Display, Write};
+use std::iter;
+use std::sync::LazyLock;
+
+use crate::util::{encode_apng, encode_pbm, encode_png, HashMap};
+
+/// The maximum number of operands an instruction can have
+pub const MAX_OPERANDS: usize = 3;
+
+/// An instruction, referring to an op in an [InstructionSet]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Instruction {
+    /// Index of the op in the instruction set
+    pub op: usize,
+
+    /// Operands of the instruction. Unused operands are 0.
+    pub operands: [i32; MAX_OPERANDS],
 }
 
 impl Instruction {
-    pub fn delay(&self) -> i32 {
-        match self {
-            Instruction::NoOp => 1,
-            Instruction::AddX(_) => 2,
+    pub const fn noop() -> Self {
+        Instruction {
+            op: NOOP,
+            operands: [0; MAX_OPERANDS],
+        }
+    }
+
+    pub const fn addx(value: i32) -> Self {
+        Instruction {
+            op: ADDX,
+            operands: [value, 0, 0],
         }
     }
 }
 
-pub fn parse(input: &str) -> Vec<Instruction> {
-    input
-        .lines()
-        .map(|line| line.split(' '))
-        .filter_map(|mut words| {
-            Some(match words.next()? {
-                "noop" => Instruction::NoOp,
-                "addx" => Instruction::AddX(words.next()?.parse().ok()?),
-                unknown => panic!("unknown instruction: {unknown}"),
-            })
-        })
-        .collect()
+/// Assemble a program using the instruction set from the puzzle
+pub fn parse(input: &str) -> Result<Vec<Instruction>, AsmError> {
+    PUZZLE_ISA.assemble(input)
 }
+
 pub fn part1(input: &str) -> i32 {
-    let instructions = parse(input);
-    let mut cycle = 1;
-    let mut reg_x: i32 = 1;
-    let mut sum = 0;
+    let instructions = parse(input).expect("failed to parse input");
+    Cpu::new(&instructions)
+        .filter(|state| (state.cycle - 20) % 40 == 0)
+        .map(|state| state.cycle * state.reg_x)
+        .sum()
+}
+
+pub const CRT_W: usize = 40;
+pub const CRT_H: usize = 6;
+pub const CRT_LETTER_W: usize = CRT_W / 8;
+
+pub fn part2(input: &str) -> String {
+    let instructions = parse(input).expect("failed to parse input");
+    recognise_text(&render(&PUZZLE_ISA, &instructions))
+}
+
+/// Run a program and return what it draws on the CRT
+pub fn render(isa: &InstructionSet, program: &[Instruction]) -> String {
+    let screen = Screen::default();
+    screen.to_text(&screen.draw(isa, program))
+}
+
+/// Draw text on a CRT screen using the font. Returns None if the text doesn't fit or contains
+/// letters that aren't in the font.
+pub fn draw_text(text: &str) -> Option<String> {
+    if text.chars().count() > CRT_W / CRT_LETTER_W {
+        return None;
+    }
+
+    let mut lines = vec![String::with_capacity(CRT_W); CRT_H];
+    for c in text.chars() {
+        let &(_, bitmap) = BITMAPS.iter().find(|&&(letter, _)| letter == c)?;
+        for (line, row) in lines.iter_mut().zip(bitmap.lines()) {
+            write!(line, "{row:.<CRT_LETTER_W$}").unwrap();
+        }
+    }
+    Some(
+        lines
+            .into_iter()
+            .map(|line| format!("{line:.<CRT_W$}\n"))
+            .collect(),
+    )
+}
+
+/// Generate a program which draws text on the CRT.
+pub fn compile_text(text: &str) -> Option<Vec<Instruction>> {
+    compile_bitmap(&draw_text(text)?)
+}
+
+/// Generate a program which makes the CRT draw a bitmap of `#`s and `.`s.
+pub fn compile_bitmap(bitmap: &str) -> Option<Vec<Instruction>> {
+    Screen::default().compile(bitmap)
+}
+
+/// The dimensions of a CRT screen. The beam draws one pixel per cycle, row by row.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Screen {
+    pub width: usize,
+    pub height: usize,
+}
+
+impl Default for Screen {
+    fn default() -> Self {
+        Screen {
+            width: CRT_W,
+            height: CRT_H,
+        }
+    }
+}
+
+impl Screen {
+    /// Run a program and return which pixels it lights up, row by row.
+    ///
+    /// Pixels are dark if the program ends early, and cycles past the end of the screen are ignored.
+    pub fn draw(&self, isa: &InstructionSet, program: &[Instruction]) -> Vec<bool> {
+        let mut pixels = vec![false; self.width * self.height];
+        for (pixel, state) in pixels.iter_mut().zip(Cpu::with_isa(isa, program)) {
+            *pixel = self.lit(state);
+        }
+        pixels
+    }
+
+    /// Whether the pixel under the beam is lit during a cycle
+    fn lit(&self, state: CycleState) -> bool {
+        let crt_x = (state.cycle - 1) as usize % self.width;
+        let sprite_pos = state.reg_x - 1..=state.reg_x + 1;
+        sprite_pos.contains(&(crt_x as i32))
+    }
+
+    /// Format pixels as lines of `#`s and `.`s
+    pub fn to_text(self, pixels: &[bool]) -> String {
+        let mut text = String::with_capacity((self.width + 1) * self.height);
+        for row in pixels.chunks(self.width) {
+            text.extend(row.iter().map(|&lit| if lit { '#' } else { '.' }));
+            text.push('\n');
+        }
+        text
+    }
 
-    for instruction in instructions {
-        for _ in 0..instruction.delay() {
-            if (cycle - 20) % 40 == 0 {
-                sum += cycle * reg_x;
+    /// Encode pixels as a PBM image, where every pixel becomes a `scale`x`scale` square
+    pub fn to_pbm(self, pixels: &[bool], scale: usize) -> Vec<u8> {
+        let pixels = self.scale(pixels, scale);
+        encode_pbm(self.width * scale, self.height * scale, &pixels)
+    }
+
+    /// Encode pixels as a PNG image, where every pixel becomes a `scale`x`scale` square
+    pub fn to_png(self, pixels: &[bool], scale: usize) -> Vec<u8> {
+        let pixels: Vec<u8> = pixels
+            .iter()
+            .map(|&lit| if lit { 255 } else { 0 })
+            .collect();
+        let pixels = self.scale(&pixels, scale);
+        encode_png(self.width * scale, self.height * scale, &pixels)
+    }
+
+    /// Animate the CRT running a program as a PNG, with one frame per cycle.
+    ///
+    /// Pixels which haven't been drawn yet are black, and the beam and the sprite on its row are
+    /// highlighted.
+    pub fn animate(
+        &self,
+        isa: &InstructionSet,
+        program: &[Instruction],
+        scale: usize,
+        delay_ms: u16,
+    ) -> Vec<u8> {
+        const UNDRAWN: u8 = 0;
+        const DARK: u8 = 48;
+        const SPRITE: u8 = 96;
+        const BEAM: u8 = 160;
+        const LIT: u8 = 255;
+
+        let mut drawn = vec![UNDRAWN; self.width * self.height];
+        let mut frames = vec![];
+        for (i, state) in Cpu::with_isa(isa, program).take(drawn.len()).enumerate() {
+            drawn[i] = if self.lit(state) { LIT } else { DARK };
+
+            let mut frame = drawn.clone();
+            let row = i - i % self.width;
+            for x in state.reg_x - 1..=state.reg_x + 1 {
+                if (0..self.width as i32).contains(&x) && frame[row + x as usize] == UNDRAWN {
+                    frame[row + x as usize] = SPRITE;
+                }
+            }
+            if frame[i] != LIT {
+                frame[i] = BEAM;
             }
-            cycle += 1;
+
+            frames.push(self.scale(&frame, scale));
         }
 
-        match instruction {
-            Instruction::NoOp => {}
-            Instruction::AddX(add) => reg_x += add,
+        if frames.is_empty() {
+            frames.push(self.scale(&drawn, scale));
         }
+        encode_apng(self.width * scale, self.height * scale, &frames, delay_ms)
     }
 
-    sum
-}
+    /// Turn every pixel into a `scale`x`scale` square
+    fn scale<T: Copy>(&self, pixels: &[T], scale: usize) -> Vec<T> {
+        let mut scaled = Vec::with_capacity(pixels.len() * scale * scale);
+        for row in pixels.chunks(self.width) {
+            let scaled_row = row.iter().flat_map(|&pixel| iter::repeat_n(pixel, scale));
+            let start = scaled.len();
+            scaled.extend(scaled_row);
+            for _ in 1..scale {
+                scaled.extend_from_within(start..start + self.width * scale);
+            }
+        }
+        scaled
+    }
 
-pub const CRT_W: usize = 40;
-pub const CRT_H: usize = 6;
-pub const CRT_LETTER_W: usize = CRT_W / 8;
+    /// Generate a program which makes the CRT draw a bitmap of `#`s and `.`s.
+    ///
+    /// Returns None if the bitmap doesn't match the screen size, or if it can't be drawn. For
+    /// example, the first pixel is always lit since X starts at 1, and X can only change at most
+    /// every other cycle.
+    pub fn compile(&self, bitmap: &str) -> Option<Vec<Instruction>> {
+        let lines: Vec<_> = bitmap.lines().collect();
+        if lines.len() != self.height || lines.iter().any(|line| line.len() != self.width) {
+            return None;
+        }
+        let pixels: Vec<bool> = lines
+            .iter()
+            .flat_map(|line| line.bytes())
+            .map(|c| c == b'#')
+            .collect();
 
-pub fn part2(input: &str) -> String {
-    let instructions = parse(input);
-    let mut crt_out = String::with_capacity((CRT_W + 1) * CRT_H);
-    let mut crt_x: usize = 0;
-    let mut reg_x: i32 = 1;
-    for instruction in instructions {
-        for _ in 0..instruction.delay() {
-            let sprit_pos = reg_x - 1..=reg_x + 1;
-            if sprit_pos.contains(&(crt_x as i32)) {
-                crt_out.push('#');
-            } else {
-                crt_out.push('.');
+        // every X outside of this range looks the same, since the sprite is completely off-screen
+        const MIN_X: i32 = -2;
+        let xs = MIN_X..=self.width as i32 + 1;
+        let index = |x: i32| (x - MIN_X) as usize;
+        let draws = |cycle: usize, x: i32| {
+            let lit = (x - (cycle % self.width) as i32).abs() <= 1;
+            pixels[cycle] == lit
+        };
+
+        // how to reach the start of a cycle with some X, as the previous X and the instruction
+        let mut from = vec![vec![None; xs.clone().count()]; pixels.len() + 1];
+        from[0][index(1)] = Some((1, Instruction::noop()));
+
+        for cycle in 0..pixels.len() {
+            for x in xs.clone() {
+                if from[cycle][index(x)].is_none() || !draws(cycle, x) {
+                    continue;
+                }
+
+                from[cycle + 1][index(x)].get_or_insert((x, Instruction::noop()));
+
+                if cycle + 2 <= pixels.len() && draws(cycle + 1, x) {
+                    for next_x in xs.clone() {
+                        from[cycle + 2][index(next_x)]
+                            .get_or_insert((x, Instruction::addx(next_x - x)));
+                    }
+                }
             }
+        }
+
+        // walk backwards from the last cycle
+        let mut x = xs
+            .clone()
+            .find(|&x| from[pixels.len()][index(x)].is_some())?;
+        let mut cycle = pixels.len();
+        let mut program = vec![];
+        while cycle > 0 {
+            let (prev_x, instruction) = from[cycle][index(x)]?;
+            program.push(instruction);
+            cycle -= PUZZLE_ISA.ops[instruction.op].cycles as usize;
+            x = prev_x;
+        }
+        program.reverse();
+
+        Some(program)
+    }
+}
+
+/// The state of the CPU during a cycle
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CycleState {
+    /// The current cycle, starting at 1
+    pub cycle: i32,
+
+    /// The value of the X register, which is only updated after an instruction has finished
+    pub reg_x: i32,
+
+    /// The instruction being executed
+    pub instruction: Instruction,
+}
+
+pub enum Breakpoint {
+    /// Break during a specific cycle
+    Cycle(i32),
+
+    /// Break during any cycle where a register matches a condition
+    Condition(Register, Box<dyn Fn(i32) -> bool>),
+
+    /// Break during the first cycle after a register has changed
+    Watch(Register),
+}
+
+/// Executes a program cycle by cycle. Iterating over it yields the state during every cycle.
+pub struct Cpu<'a> {
+    isa: &'a InstructionSet,
+    program: &'a [Instruction],
+    machine: Machine,
 
-            crt_x += 1;
-            if crt_x >= CRT_W {
-                crt_x = 0;
-                crt_out.push('\n');
+    /// The registers during the last executed cycle, for the [Breakpoint::Watch] breakpoints
+    prev_registers: Vec<i32>,
+
+    /// The number of cycles the current instruction has been executing for
+    progress: i32,
+
+    cycle: i32,
+    breakpoints: Vec<Breakpoint>,
+}
+
+impl<'a> Cpu<'a> {
+    pub fn new(program: &'a [Instruction]) -> Self {
+        Self::with_isa(&PUZZLE_ISA, program)
+    }
+
+    /// Panics if the program isn't supported by the instruction set
+    pub fn with_isa(isa: &'a InstructionSet, program: &'a [Instruction]) -> Self {
+        assert!(
+            isa.supports(program),
+            "program isn't valid for the instruction set"
+        );
+        let mut registers = vec![0; isa.registers.len()];
+        registers[X] = 1;
+        Cpu {
+            isa,
+            program,
+            prev_registers: registers.clone(),
+            machine: Machine { registers, pc: 0 },
+            progress: 0,
+            cycle: 1,
+            breakpoints: vec![],
+        }
+    }
+
+    pub fn add_breakpoint(&mut self, breakpoint: Breakpoint) {
+        self.breakpoints.push(breakpoint);
+    }
+
+    /// The value of the X register, after the last executed cycle
+    pub fn reg_x(&self) -> i32 {
+        self.machine.registers[X]
+    }
+
+    /// The value of a register, after the last executed cycle
+    pub fn register(&self, register: Register) -> i32 {
+        self.machine.registers[register]
+    }
+
+    /// Run until the end of the program, or until a breakpoint is hit. Returns the state during
+    /// the cycle that hit the breakpoint.
+    pub fn run(&mut self) -> Option<CycleState> {
+        loop {
+            // registers are only updated at the end of a cycle, so these are their values during it
+            let registers = &self.machine.registers;
+            let hit = self.breakpoints.iter().any(|breakpoint| match breakpoint {
+                &Breakpoint::Cycle(cycle) => self.cycle == cycle,
+                Breakpoint::Condition(r, condition) => condition(registers[*r]),
+                Breakpoint::Watch(r) => registers[*r] != self.prev_registers[*r],
+            });
+
+            let state = self.next()?;
+            if hit {
+                return Some(state);
             }
         }
+    }
+}
 
-        match instruction {
-            Instruction::NoOp => {}
-            Instruction::AddX(add) => reg_x += add,
+impl Iterator for Cpu<'_> {
+    type Item = CycleState;
+
+    /// Execute a single cycle
+    fn next(&mut self) -> Option<CycleState> {
+        let &instruction = self.program.get(self.machine.pc)?;
+        let op = &self.isa.ops[instruction.op];
+        let state = CycleState {
+            cycle: self.cycle,
+            reg_x: self.reg_x(),
+            instruction,
+        };
+
+        self.prev_registers.clone_from(&self.machine.registers);
+        self.cycle += 1;
+        self.progress += 1;
+        if self.progress == op.cycles {
+            self.machine.pc += 1;
+            self.progress = 0;
+            (op.effect)(&mut self.machine, &instruction.operands);
         }
+
+        Some(state)
     }
+}
+
+/// Index of a register in an [InstructionSet]
+pub type Register = usize;
+
+/// The X register, which controls the sprite position
+pub const X: Register = 0;
+
+/// Index of `noop` in the puzzle instruction set
+pub const NOOP: usize = 0;
+
+/// Index of `addx` in the puzzle instruction set
+pub const ADDX: usize = 1;
 
-    parse_crt(crt_out)
+/// The instruction set from the puzzle, which only has `noop` and `addx`
+pub static PUZZLE_ISA: LazyLock<InstructionSet> = LazyLock::new(InstructionSet::default);
+
+/// The state that an instruction can modify
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Machine {
+    pub registers: Vec<i32>,
+
+    /// Index of the next instruction. Has already been incremented when an effect runs.
+    pub pc: usize,
+}
+
+/// Runs when an instruction has finished executing
+pub type Effect = fn(&mut Machine, &[i32; MAX_OPERANDS]);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Operand {
+    /// A constant number
+    Immediate,
+
+    /// The name of a register, stored as a [Register]
+    Register,
+
+    /// A label, stored as the index of the instruction it points to
+    Label,
+}
+
+#[derive(Clone, Copy)]
+pub struct OpSpec {
+    pub mnemonic: &'static str,
+    pub operands: &'static [Operand],
+
+    /// The number of cycles the instruction takes to execute
+    pub cycles: i32,
+    pub effect: Effect,
+}
+
+/// A table of ops and registers, which can be extended with new ones
+#[derive(Clone)]
+pub struct InstructionSet {
+    ops: Vec<OpSpec>,
+    registers: Vec<String>,
+}
+
+impl Default for InstructionSet {
+    fn default() -> Self {
+        InstructionSet {
+            ops: vec![
+                OpSpec {
+                    mnemonic: "noop",
+                    operands: &[],
+                    cycles: 1,
+                    effect: |_, _| {},
+                },
+                OpSpec {
+                    mnemonic: "addx",
+                    operands: &[Operand::Immediate],
+                    cycles: 2,
+                    effect: |m, &[value, ..]| m.registers[X] += value,
+                },
+            ],
+            registers: vec!["x".to_string()],
+        }
+    }
 }
 
-/// Try to parse the output of the CRT display as text
-fn parse_crt(crt: String) -> String {
-    let mut parsed = String::with_capacity(8);
-    'crt: for start in (0..CRT_W).step_by(CRT_LETTER_W) {
-        'letters: for &(letter, bitmap) in BITMAPS {
-            for (crt_line, bitmap_line) in crt.lines().zip(bitmap.lines()) {
-                let x_range = start..start + CRT_LETTER_W;
-                if &crt_line[x_range] != bitmap_line {
-                    continue 'letters;
+impl InstructionSet {
+    /// Add a new op, returning its index
+    pub fn add_op(&mut self, op: OpSpec) -> usize {
+        assert!(op.cycles > 0, "ops must take at least one cycle");
+        assert!(op.operands.len() <= MAX_OPERANDS, "too many operands");
+        assert!(self.op(op.mnemonic).is_none(), "duplicate op");
+        self.ops.push(op);
+        self.ops.len() - 1
+    }
+
+    /// Add a new register, initialised to 0, returning its index
+    pub fn add_register(&mut self, name: &str) -> Register {
+        assert!(self.register(name).is_none(), "duplicate register");
+        self.registers.push(name.to_string());
+        self.registers.len() - 1
+    }
+
+    /// Whether every op and register in a program is in this instruction set, and every label
+    /// points into the program or to its end
+    pub fn supports(&self, program: &[Instruction]) -> bool {
+        program.iter().all(|instruction| {
+            self.ops.get(instruction.op).is_some_and(|spec| {
+                (spec.operands.iter())
+                    .zip(instruction.operands)
+                    .all(|(&kind, operand)| {
+                        let index = usize::try_from(operand);
+                        match kind {
+                            Operand::Immediate => true,
+                            Operand::Register => index.is_ok_and(|r| r < self.registers.len()),
+                            Operand::Label => index.is_ok_and(|address| address <= program.len()),
+                        }
+                    })
+            })
+        })
+    }
+
+    /// Look up an op by its mnemonic
+    pub fn op(&self, mnemonic: &str) -> Option<usize> {
+        self.ops.iter().position(|op| op.mnemonic == mnemonic)
+    }
+
+    /// Look up a register by its name
+    pub fn register(&self, name: &str) -> Option<Register> {
+        self.registers.iter().position(|r| r == name)
+    }
+
+    /// Assemble a program, one instruction per line.
+    ///
+    /// Operands are separated by spaces or commas, comments start with `;`, and a line can be
+    /// prefixed with any number of `label:`s.
+    pub fn assemble(&self, src: &str) -> Result<Vec<Instruction>, AsmError> {
+        let lines: Vec<_> = (1..).zip(src.lines()).collect();
+
+        // first pass: find the address of every label
+        let mut labels = HashMap::default();
+        let mut address: usize = 0;
+        for &(line, text) in &lines {
+            let (line_labels, words) = split_asm_line(text);
+            for label in line_labels {
+                if labels.insert(label, address).is_some() {
+                    let label = label.to_string();
+                    return Err(AsmError::DuplicateLabel { line, label });
+                }
+            }
+            if !words.is_empty() {
+                address += 1;
+            }
+        }
+
+        // second pass: encode the instructions
+        let mut program = vec![];
+        for (line, text) in lines {
+            let (_, words) = split_asm_line(text);
+            let Some((&mnemonic, operands)) = words.split_first() else {
+                continue;
+            };
+
+            let op = self.op(mnemonic).ok_or_else(|| AsmError::UnknownMnemonic {
+                line,
+                mnemonic: mnemonic.to_string(),
+            })?;
+            let spec = &self.ops[op];
+            if operands.len() != spec.operands.len() {
+                return Err(AsmError::OperandCount {
+                    line,
+                    expected: spec.operands.len(),
+                    found: operands.len(),
+                });
+            }
+
+            let mut instruction = Instruction {
+                op,
+                operands: [0; MAX_OPERANDS],
+            };
+            for ((encoded, &operand), kind) in (instruction.operands.iter_mut())
+                .zip(operands)
+                .zip(spec.operands)
+            {
+                let value = match kind {
+                    Operand::Immediate => operand.parse().ok(),
+                    Operand::Register => self.register(operand).map(|r| r as i32),
+                    Operand::Label => labels.get(operand).map(|&address| address as i32),
+                };
+                *encoded = value.ok_or_else(|| AsmError::InvalidOperand {
+                    line,
+                    operand: operand.to_string(),
+                })?;
+            }
+            program.push(instruction);
+        }
+
+        Ok(program)
+    }
+
+    /// Turn a program back into source code. Jump targets get generated labels.
+    pub fn disassemble(&self, program: &[Instruction]) -> String {
+        let mut labels = BTreeMap::new();
+        for instruction in program {
+            let spec = &self.ops[instruction.op];
+            for (&operand, kind) in instruction.operands.iter().zip(spec.operands) {
+                if *kind == Operand::Label {
+                    labels.insert(operand as usize, String::new());
+                }
+            }
+        }
+        for (i, name) in labels.values_mut().enumerate() {
+            *name = format!("l{i}");
+        }
+
+        let mut src = String::new();
+        for (address, instruction) in program.iter().enumerate() {
+            if let Some(label) = labels.get(&address) {
+                writeln!(src, "{label}:").unwrap();
+            }
+
+            let spec = &self.ops[instruction.op];
+            src.push_str(spec.mnemonic);
+            for (&operand, kind) in instruction.operands.iter().zip(spec.operands) {
+                match kind {
+                    Operand::Immediate => write!(src, " {operand}"),
+                    Operand::Register => write!(src, " {}", self.registers[operand as usize]),
+                    Operand::Label => write!(src, " {}", labels[&(operand as usize)]),
                 }
+                .unwrap();
             }
+            src.push('\n');
+        }
 
-            parsed.push(letter);
-            continue 'crt;
+        // labels can also point to the end of the program
+        if let Some(label) = labels.get(&program.len()) {
+            writeln!(src, "{label}:").unwrap();
         }
 
-        // failed to parse letter, give up and return crt output
-        return crt;
+        src
     }
+}
+
+/// Split a line of assembly into its labels and the remaining words
+fn split_asm_line(text: &str) -> (Vec<&str>, Vec<&str>) {
+    let code = text.split(';').next().unwrap_or_default();
+    let mut words: Vec<_> = (code.split([' ', '\t', ',']))
+        .filter(|word| !word.is_empty())
+        .collect();
+    let labels = words
+        .iter()
+        .take_while(|word| word.ends_with(':'))
+        .map(|word| word.trim_end_matches(':'))
+        .collect::<Vec<_>>();
+    words.drain(..labels.len());
+    (labels, words)
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum AsmError {
+    UnknownMnemonic {
+        line: usize,
+        mnemonic: String,
+    },
+    OperandCount {
+        line: usize,
+        expected: usize,
+        found: usize,
+    },
+    InvalidOperand {
+        line: usize,
+        operand: String,
+    },
+    DuplicateLabel {
+        line: usize,
+        label: String,
+    },
+}
 
-    parsed
+impl Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AsmError::UnknownMnemonic { line, mnemonic } => {
+                write!(f, "line {line}: unknown instruction: {mnemonic}")
+            }
+            AsmError::OperandCount {
+                line,
+                expected,
+                found,
+            } => write!(
+                f,
+                "line {line}: expected {expected} operands, found {found}"
+            ),
+            AsmError::InvalidOperand { line, operand } => {
+                write!(f, "line {line}: invalid operand: {operand}")
+            }
+            AsmError::DuplicateLabel { line, label } => {
+                write!(f, "line {line}: label {label} is already defined")
+            }
+        }
+    }
+}
+
+impl Error for AsmError {}
+
+/// Marker for letters which aren't in the CRT font
+pub const UNRECOGNISED: char = '?';
+
+/// Recognise the letters on a CRT screen, replacing unknown ones with [UNRECOGNISED].
+///
+/// Missing pixels, e.g. from trimmed trailing whitespace, count as unlit. The blank column after
+/// every letter is ignored unless the letter uses it, and blank letters at the end are skipped.
+pub fn recognise_text(crt: &str) -> String {
+    let lines: Vec<&[u8]> = crt.lines().map(|line| line.trim_end().as_bytes()).collect();
+    let lit = |x: usize, y: usize| lines.get(y).and_then(|line| line.get(x)) == Some(&b'#');
+    let width = lines.iter().map(|line| line.len()).max().unwrap_or(0);
+
+    let mut text: String = (0..width)
+        .step_by(CRT_LETTER_W)
+        .map(|start| {
+            let letter = start..start + CRT_LETTER_W;
+            if !(0..CRT_H).any(|y| letter.clone().any(|x| lit(x, y))) {
+                return ' ';
+            }
+
+            let matches = |bitmap: &str| {
+                (bitmap.lines().enumerate()).all(|(y, row)| {
+                    row.bytes().enumerate().all(|(x, pixel)| match pixel {
+                        b'#' => lit(start + x, y),
+                        _ if x == CRT_LETTER_W - 1 => true,
+                        _ => !lit(start + x, y),
+                    })
+                })
+            };
+
+            (BITMAPS.iter())
+                .find(|(_, bitmap)| matches(bitmap))
+                .map_or(UNRECOGNISED, |&(letter, _)| letter)
+        })
+        .collect();
+
+    text.truncate(text.trim_end().len());
+    text
 }
 
 /// Bitmaps representing the CRT font
 const BITMAPS: &[(char, &str)] = &[
+    (
+        'A',
+        ".##..\n\
+         #..#.\n\
+         #..#.\n\
+         ####.\n\
+         #..#.\n\
+         #..#.",
+    ),
+    (
+        'B',
+        "###..\n\
+         #..#.\n\
+         ###..\n\
+         #..#.\n\
+         #..#.\n\
+         ###..",
+    ),
+    (
+        'C',
+        ".##..\n\
+         #..#.\n\
+         #....\n\
+         #....\n\
+         #..#.\n\
+         .##..",
+    ),
+    (
+        'E',
+        "####.\n\
+         #....\n\
+         ###..\n\
+         #....\n\
+         #....\n\
+         ####.",
+    ),
     (
         'F',
         "####.\n\
@@ -117,22 +785,67 @@ const BITMAPS: &[(char, &str)] = &[
          #....",
     ),
     (
-        'Z',
-        "####.\n\
-         ...#.\n\
+        'G',
+        ".##..\n\
+         #..#.\n\
+         #....\n\
+         #.##.\n\
+         #..#.\n\
+         .###.",
+    ),
+    (
+        'H',
+        "#..#.\n\
+         #..#.\n\
+         ####.\n\
+         #..#.\n\
+         #..#.\n\
+         #..#.",
+    ),
+    (
+        'I',
+        ".###.\n\
          ..#..\n\
-         .#...\n\
+         ..#..\n\
+         ..#..\n\
+         ..#..\n\
+         .###.",
+    ),
+    (
+        'J',
+        "..##.\n\
+         ...#.\n\
+         ...#.\n\
+         ...#.\n\
+         #..#.\n\
+         .##..",
+    ),
+    (
+        'K',
+        "#..#.\n\
+         #.#..\n\
+         ##...\n\
+         #.#..\n\
+         #.#..\n\
+         #..#.",
+    ),
+    (
+        'L',
+        "#....\n\
+         #....\n\
+         #....\n\
+         #....\n\
          #....\n\
          ####.",
     ),
     (
-        'B',
-        "###..\n\
+        'O',
+        ".##..\n\
          #..#.\n\
-         ###..\n\
          #..#.\n\
          #..#.\n\
-         ###..",
+         #..#.\n\
+         .##..",
     ),
     (
         'P',
@@ -143,11 +856,370 @@ const BITMAPS: &[(char, &str)] = &[
          #....\n\
          #....",
     ),
+    (
+        'R',
+        "###..\n\
+         #..#.\n\
+         #..#.\n\
+         ###..\n\
+         #.#..\n\
+         #..#.",
+    ),
+    (
+        'S',
+        ".###.\n\
+         #....\n\
+         #....\n\
+         .##..\n\
+         ...#.\n\
+         ###..",
+    ),
+    (
+        'U',
+        "#..#.\n\
+         #..#.\n\
+         #..#.\n\
+         #..#.\n\
+         #..#.\n\
+         .##..",
+    ),
+    (
+        'Y',
+        "#...#\n\
+         #...#\n\
+         .#.#.\n\
+         ..#..\n\
+         ..#..\n\
+         ..#..",
+    ),
+    (
+        'Z',
+        "####.\n\
+         ...#.\n\
+         ..#..\n\
+         .#...\n\
+         #....\n\
+         ####.",
+    ),
 ];
 
 #[cfg(test)]
 mod tests {
-    use super::{part1, part2};
+    use super::{
+        compile_bitmap, compile_text, draw_text, parse, part1, part2, recognise_text, render,
+        AsmError, Breakpoint, Cpu, CycleState, Instruction, InstructionSet, OpSpec, Operand,
+        Screen, BITMAPS, PUZZLE_ISA, UNRECOGNISED, X,
+    };
+    use crate::util::Lcg;
+
+    fn draw(text: &str) -> String {
+        draw_text(text).unwrap()
+    }
+
+    #[test]
+    pub fn test_font() {
+        let alphabet: String = BITMAPS.iter().map(|&(letter, _)| letter).collect();
+        assert_eq!(alphabet, "ABCEFGHIJKLOPRSUYZ");
+        for text in alphabet.as_bytes().chunks(8) {
+            let text = std::str::from_utf8(text).unwrap();
+            assert_eq!(recognise_text(&draw(text)), text);
+        }
+
+        // trailing whitespace and missing pixels at the end of lines
+        let screen = draw("HELLO").replace('.', " ");
+        let screen: String = screen
+            .lines()
+            .map(|line| line.trim_end().to_string() + "\n")
+            .collect();
+        assert_eq!(recognise_text(&screen), "HELLO");
+
+        // unknown letters are marked individually
+        let screen = draw("HELLO").replacen("#..#.", "##.#.", 1);
+        assert_eq!(recognise_text(&screen), format!("{UNRECOGNISED}ELLO"));
+    }
+
+    #[test]
+    pub fn test_cpu() {
+        let program = parse("noop\naddx 3\naddx -5\n").unwrap();
+        let trace: Vec<_> = Cpu::new(&program)
+            .map(|state| (state.cycle, state.reg_x, state.instruction))
+            .collect();
+        assert_eq!(
+            trace,
+            [
+                (1, 1, Instruction::noop()),
+                (2, 1, Instruction::addx(3)),
+                (3, 1, Instruction::addx(3)),
+                (4, 4, Instruction::addx(-5)),
+                (5, 4, Instruction::addx(-5)),
+            ]
+        );
+
+        let mut cpu = Cpu::new(&program);
+        cpu.run();
+        assert_eq!(cpu.reg_x(), -1);
+    }
+
+    #[test]
+    pub fn test_breakpoints() {
+        let program = parse(include_str!("test-input")).unwrap();
+        let mut cpu = Cpu::new(&program);
+        cpu.add_breakpoint(Breakpoint::Cycle(20));
+        cpu.add_breakpoint(Breakpoint::Cycle(60));
+        let state = |cycle, reg_x, instruction| {
+            Some(CycleState {
+                cycle,
+                reg_x,
+                instruction,
+            })
+        };
+        assert_eq!(cpu.run(), state(20, 21, Instruction::addx(-1)));
+        assert_eq!(cpu.run(), state(60, 19, Instruction::addx(-3)));
+
+        cpu.add_breakpoint(Breakpoint::Condition(X, Box::new(|x| x < 0)));
+        assert_eq!(cpu.run(), state(210, -1, Instruction::addx(22)));
+
+        let mut cpu = Cpu::new(&program);
+        cpu.add_breakpoint(Breakpoint::Watch(X));
+        assert_eq!(cpu.run(), state(3, 16, Instruction::addx(-11)));
+        assert_eq!(cpu.run(), state(5, 5, Instruction::addx(6)));
+        assert_eq!(cpu.count(), 240 - 5);
+
+        // a change during the cycle that hit another breakpoint is still caught by the next run
+        let mut cpu = Cpu::new(&program);
+        cpu.add_breakpoint(Breakpoint::Cycle(2));
+        assert_eq!(cpu.run(), state(2, 1, Instruction::addx(15)));
+        cpu.add_breakpoint(Breakpoint::Watch(X));
+        assert_eq!(cpu.run(), state(3, 16, Instruction::addx(-11)));
+    }
+
+    #[test]
+    pub fn test_custom_isa() {
+        let mut isa = InstructionSet::default();
+        let y = isa.add_register("y");
+        isa.add_op(OpSpec {
+            mnemonic: "nop2",
+            operands: &[],
+            cycles: 2,
+            effect: |_, _| {},
+        });
+        let inc = isa.add_op(OpSpec {
+            mnemonic: "inc",
+            operands: &[Operand::Register],
+            cycles: 2,
+            effect: |m, &[r, ..]| m.registers[r as usize] += 1,
+        });
+
+        // the custom ops take the same number of cycles as four noops
+        let input = include_str!("test-input");
+        let program = isa.assemble(&format!("nop2\ninc y\n{input}")).unwrap();
+        let expected = parse(&format!("noop\nnoop\nnoop\nnoop\n{input}")).unwrap();
+        assert_eq!(render(&isa, &program), render(&PUZZLE_ISA, &expected));
+
+        let mut cpu = Cpu::with_isa(&isa, &program);
+        cpu.add_breakpoint(Breakpoint::Watch(y));
+        let state = cpu.run().unwrap();
+        assert_eq!((state.cycle, cpu.register(X), cpu.register(y)), (5, 1, 1));
+        cpu.add_breakpoint(Breakpoint::Condition(y, Box::new(|y| y != 1)));
+        assert_eq!(cpu.run(), None);
+
+        // programs are checked against the instruction set before they run
+        assert!(isa.supports(&program));
+        assert!(!PUZZLE_ISA.supports(&program));
+        let instruction = |op, operand| Instruction {
+            op,
+            operands: [operand, 0, 0],
+        };
+        assert!(!isa.supports(&[instruction(inc, 2)]));
+
+        // jumps may only target the program or its end
+        let jmp = isa.add_op(OpSpec {
+            mnemonic: "jmp",
+            operands: &[Operand::Label],
+            cycles: 1,
+            effect: |m, &[target, ..]| m.pc = target as usize,
+        });
+        assert!(isa.supports(&[instruction(jmp, 1)]));
+        assert!(!isa.supports(&[instruction(jmp, 2)]));
+        assert!(!isa.supports(&[instruction(jmp, -1)]));
+
+        // a register changing in back-to-back cycles hits a watch in both of them
+        let inc1 = isa.add_op(OpSpec {
+            mnemonic: "inc1",
+            operands: &[Operand::Register],
+            cycles: 1,
+            effect: |m, &[r, ..]| m.registers[r as usize] += 1,
+        });
+        let program = isa.assemble("inc1 y\ninc1 y\nnoop\nnoop\n").unwrap();
+        assert_eq!(program[0], instruction(inc1, y as i32));
+        let mut cpu = Cpu::with_isa(&isa, &program);
+        cpu.add_breakpoint(Breakpoint::Watch(y));
+        assert_eq!(cpu.run().map(|state| state.cycle), Some(2));
+        assert_eq!(cpu.run().map(|state| state.cycle), Some(3));
+        assert_eq!(cpu.run(), None);
+    }
+
+    #[test]
+    pub fn test_assembler() {
+        let mut isa = InstructionSet::default();
+        let y = isa.add_register("y");
+        isa.add_op(OpSpec {
+            mnemonic: "add",
+            operands: &[Operand::Register, Operand::Immediate],
+            cycles: 1,
+            effect: |m, &[r, value, _]| m.registers[r as usize] += value,
+        });
+        isa.add_op(OpSpec {
+            mnemonic: "jnz",
+            operands: &[Operand::Register, Operand::Label],
+            cycles: 1,
+            effect: |m, &[r, target, _]| {
+                if m.registers[r as usize] != 0 {
+                    m.pc = target as usize;
+                }
+            },
+        });
+
+        let src = "\
+            ; add 2 to x, three times\n\
+                    add y, 3\n\
+            loop:   addx 2\n\
+                    add y, -1\n\
+                    jnz y, loop ; y is the loop counter\n\
+            end:\n";
+        let program = isa.assemble(src).unwrap();
+        let mut cpu = Cpu::with_isa(&isa, &program);
+        assert_eq!(cpu.by_ref().count(), 13);
+        assert_eq!(cpu.reg_x(), 7);
+        assert_eq!(cpu.register(y), 0);
+
+        let disassembly = isa.disassemble(&program);
+        assert_eq!(disassembly, "add y 3\nl0:\naddx 2\nadd y -1\njnz y l0\n");
+        assert_eq!(isa.assemble(&disassembly), Ok(program));
+
+        let error = |src| isa.assemble(src).unwrap_err();
+        assert_eq!(
+            error("noop\nmul x 2"),
+            AsmError::UnknownMnemonic {
+                line: 2,
+                mnemonic: "mul".to_string()
+            }
+        );
+        assert_eq!(
+            error("addx"),
+            AsmError::OperandCount {
+                line: 1,
+                expected: 1,
+                found: 0
+            }
+        );
+        assert_eq!(
+            error("add z 1"),
+            AsmError::InvalidOperand {
+                line: 1,
+                operand: "z".to_string()
+            }
+        );
+        assert_eq!(
+            error("jnz x nowhere"),
+            AsmError::InvalidOperand {
+                line: 1,
+                operand: "nowhere".to_string()
+            }
+        );
+        assert_eq!(
+            error("a: noop\na: noop"),
+            AsmError::DuplicateLabel {
+                line: 2,
+                label: "a".to_string()
+            }
+        );
+    }
+
+    #[test]
+    pub fn test_disassembler() {
+        let input = include_str!("test-input");
+        let program = parse(input).unwrap();
+        assert_eq!(PUZZLE_ISA.disassemble(&program), input);
+    }
+
+    #[test]
+    pub fn test_compile() {
+        let mut rng = Lcg(0x2022_1210);
+        let alphabet: Vec<char> = BITMAPS.iter().map(|&(letter, _)| letter).collect();
+        let mut compiled = 0;
+        for _ in 0..100 {
+            let text: String = (0..8)
+                .map(|_| alphabet[rng.below(alphabet.len() as u64) as usize])
+                .collect();
+
+            // not every combination of letters can be drawn, since X can't change every cycle
+            if let Some(program) = compile_text(&text) {
+                let src = PUZZLE_ISA.disassemble(&program);
+                assert_eq!(part2(&src), text);
+                compiled += 1;
+            }
+        }
+        assert!(compiled > 0);
+
+        // answers from real puzzle inputs
+        for text in ["EHPZPJGL", "RZHFGJCB", "PLPAFBCL", "BUCACBUZ", "ZKJFBJFZ"] {
+            let program = compile_text(text).expect(text);
+            assert_eq!(recognise_text(&render(&PUZZLE_ISA, &program)), text);
+        }
+
+        let bitmap = render(&PUZZLE_ISA, &parse(include_str!("test-input")).unwrap());
+        let program = compile_bitmap(&bitmap).unwrap();
+        assert_eq!(render(&PUZZLE_ISA, &program), bitmap);
+
+        assert_eq!(compile_text("HELLO WORLD"), None);
+        assert_eq!(compile_text("CAB"), None);
+        assert_eq!(compile_bitmap(&bitmap[1..]), None);
+        // the first two pixels are always lit, since X is 1 during the first two cycles
+        assert_eq!(compile_bitmap(&bitmap.replacen('#', ".", 1)), None);
+        assert_eq!(compile_bitmap(&bitmap.replacen("##", "#.", 1)), None);
+    }
+
+    #[test]
+    pub fn test_screen() {
+        let program = parse(include_str!("test-input")).unwrap();
+        let screen = Screen::default();
+        let pixels = screen.draw(&PUZZLE_ISA, &program);
+        assert_eq!(screen.to_text(&pixels), render(&PUZZLE_ISA, &program));
+
+        // the program expects a width of 40, so the sprite is off-screen for every other row
+        let narrow = Screen {
+            width: 20,
+            height: 12,
+        };
+        let text = narrow.to_text(&narrow.draw(&PUZZLE_ISA, &program));
+        assert_eq!(text.lines().count(), 12);
+        assert!(
+            text.starts_with("##..##..##..##..##..\n....................\n###...###...###...##\n")
+        );
+
+        // short programs leave the rest of the screen dark
+        let text = screen.to_text(&screen.draw(&PUZZLE_ISA, &program[..3]));
+        assert!(text.starts_with("##..##...."));
+        assert_eq!(text.matches('#').count(), 4);
+
+        let pbm = screen.to_pbm(&pixels, 2);
+        assert!(pbm.starts_with(b"P4\n80 12\n"));
+        assert_eq!(pbm.len(), b"P4\n80 12\n".len() + 10 * 12);
+        assert_eq!(
+            pbm[10..20],
+            [0x0f, 0x0f, 0x0f, 0x0f, 0x0f, 0x0f, 0x0f, 0x0f, 0x0f, 0x0f]
+        );
+
+        let png = screen.to_png(&pixels, 3);
+        assert!(png.starts_with(b"\x89PNG\r\n\x1a\n\0\0\0\x0dIHDR\0\0\0\x78\0\0\0\x12"));
+        assert!(png.ends_with(b"IEND\xae\x42\x60\x82"));
+
+        let apng = screen.animate(&PUZZLE_ISA, &program, 1, 50);
+        assert_eq!(apng.windows(4).filter(|w| w == b"fcTL").count(), 240);
+        assert_eq!(apng.windows(4).filter(|w| w == b"fdAT").count(), 239);
+        assert!(apng.ends_with(b"IEND\xae\x42\x60\x82"));
+    }
 
     #[test]
     pub fn test_part1() {
@@ -158,8 +1230,9 @@ mod tests {
     #[test]
     pub fn test_part2() {
         let input = include_str!("test-input");
+        assert_eq!(part2(input), UNRECOGNISED.to_string().repeat(8));
         assert_eq!(
-            part2(input),
+            render(&PUZZLE_ISA, &parse(input).unwrap()),
             "##..##..##..##..##..##..##..##..##..##..\n\
              ###...###...###...###...###...###...###.\n\
              ####....####....####....####....####....\n\
@@ -1,4 +1,10 @@
+use crate::util::HashMap;
+use num_bigint::{BigInt, Sign};
+use rayon::prelude::*;
 use std::collections::VecDeque;
+use std::fmt::{self, Debug, Display, Write};
+use std::iter::Peekable;
+use std::vec;
 
 pub struct Monkey {
     items: VecDeque<i64>,
@@ -12,12 +18,246 @@ pub enum Operation {
     Mul(i64),
     Add(i64),
     Square,
+
+    /// Any other expression, which is slower to evaluate
+    Expr(Expr),
+}
+
+impl Operation {
+    /// Parse the right hand side of an operation, e.g. `old * 19`
+    pub fn parse(s: &str) -> Option<Self> {
+        use {BinOp::*, Expr::*};
+
+        Some(match parse_expr(s)? {
+            Binary(l, op, r) => match (*l, op, *r) {
+                (Old, Mul, Old) => Operation::Square,
+                (Old, Mul, Const(n)) | (Const(n), Mul, Old) => Operation::Mul(n),
+                (Old, Add, Const(n)) | (Const(n), Add, Old) => Operation::Add(n),
+                (l, op, r) => Operation::Expr(Binary(Box::new(l), op, Box::new(r))),
+            },
+            expr => Operation::Expr(expr),
+        })
+    }
+
+    /// Whether the operation contains a division
+    pub fn divides(&self) -> bool {
+        match self {
+            Operation::Expr(expr) => expr.divides(),
+            _ => false,
+        }
+    }
+
+    pub fn apply<W: Worry>(&self, old: &W) -> Result<W, OperationError> {
+        let new = match self {
+            Operation::Square => old.checked_mul(old),
+            &Operation::Mul(mul) => old.checked_mul(&W::from_i64(mul)),
+            &Operation::Add(add) => old.checked_add(&W::from_i64(add)),
+            Operation::Expr(expr) => return expr.eval(old),
+        };
+        new.ok_or(OperationError::Overflow)
+    }
+}
+
+/// Why an operation couldn't be applied to a worry level
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OperationError {
+    Overflow,
+    DivisionByZero,
 }
 
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Expr {
+    Old,
+    Const(i64),
+    Binary(Box<Expr>, BinOp, Box<Expr>),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BinOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+}
+
+impl Expr {
+    fn divides(&self) -> bool {
+        match self {
+            Expr::Binary(l, op, r) => *op == BinOp::Div || l.divides() || r.divides(),
+            _ => false,
+        }
+    }
+
+    pub fn eval<W: Worry>(&self, old: &W) -> Result<W, OperationError> {
+        match self {
+            Expr::Old => Ok(old.clone()),
+            &Expr::Const(n) => Ok(W::from_i64(n)),
+            Expr::Binary(l, op, r) => {
+                let (l, r) = (l.eval(old)?, r.eval(old)?);
+                let result = match op {
+                    BinOp::Add => l.checked_add(&r),
+                    BinOp::Sub => l.checked_sub(&r),
+                    BinOp::Mul => l.checked_mul(&r),
+                    BinOp::Div if r.is_zero() => return Err(OperationError::DivisionByZero),
+                    BinOp::Div => l.checked_div(&r),
+                };
+                result.ok_or(OperationError::Overflow)
+            }
+        }
+    }
+}
+
+/// A number type for worry levels, where every operation returns None if it overflows
+pub trait Worry: Clone + Debug + Display {
+    fn from_i64(n: i64) -> Self;
+    fn checked_add(&self, rhs: &Self) -> Option<Self>;
+    fn checked_sub(&self, rhs: &Self) -> Option<Self>;
+    fn checked_mul(&self, rhs: &Self) -> Option<Self>;
+
+    /// Returns None on overflow or division by zero
+    fn checked_div(&self, rhs: &Self) -> Option<Self>;
+
+    fn is_zero(&self) -> bool;
+
+    /// The remainder of dividing by a non-zero number, with the same sign as `self`
+    fn rem_i64(&self, rhs: i64) -> i64;
+}
+
+macro_rules! impl_worry {
+    ($($t:ty),*) => {$(
+        impl Worry for $t {
+            fn from_i64(n: i64) -> Self {
+                n.into()
+            }
+
+            fn checked_add(&self, rhs: &Self) -> Option<Self> {
+                <$t>::checked_add(*self, *rhs)
+            }
+
+            fn checked_sub(&self, rhs: &Self) -> Option<Self> {
+                <$t>::checked_sub(*self, *rhs)
+            }
+
+            fn checked_mul(&self, rhs: &Self) -> Option<Self> {
+                <$t>::checked_mul(*self, *rhs)
+            }
+
+            fn checked_div(&self, rhs: &Self) -> Option<Self> {
+                <$t>::checked_div(*self, *rhs)
+            }
+
+            fn is_zero(&self) -> bool {
+                *self == 0
+            }
+
+            fn rem_i64(&self, rhs: i64) -> i64 {
+                (*self % <$t>::from(rhs)) as i64
+            }
+        }
+    )*};
+}
+
+impl_worry!(i64, i128);
+
+/// Arbitrary precision worry levels, which never overflow
+impl Worry for BigInt {
+    fn from_i64(n: i64) -> Self {
+        n.into()
+    }
+
+    fn checked_add(&self, rhs: &Self) -> Option<Self> {
+        Some(self + rhs)
+    }
+
+    fn checked_sub(&self, rhs: &Self) -> Option<Self> {
+        Some(self - rhs)
+    }
+
+    fn checked_mul(&self, rhs: &Self) -> Option<Self> {
+        Some(self * rhs)
+    }
+
+    fn checked_div(&self, rhs: &Self) -> Option<Self> {
+        (!rhs.is_zero()).then(|| self / rhs)
+    }
+
+    fn is_zero(&self) -> bool {
+        self.sign() == Sign::NoSign
+    }
+
+    fn rem_i64(&self, rhs: i64) -> i64 {
+        i64::try_from(self % rhs).expect("remainder is smaller than the divisor")
+    }
+}
+
+/// Parse an arithmetic expression of `old`, constants, `+ - * /` and parentheses
+pub fn parse_expr(s: &str) -> Option<Expr> {
+    const SYMBOLS: &str = "+-*/()";
+
+    let mut tokens = vec![];
+    let mut rest = s.trim_start();
+    while let Some(c) = rest.chars().next() {
+        let len = if SYMBOLS.contains(c) {
+            1
+        } else {
+            rest.find(|c: char| c.is_whitespace() || SYMBOLS.contains(c))
+                .unwrap_or(rest.len())
+        };
+        let (token, tail) = rest.split_at(len);
+        tokens.push(token);
+        rest = tail.trim_start();
+    }
+
+    let mut tokens = tokens.into_iter().peekable();
+    let expr = parse_sum(&mut tokens)?;
+    tokens.next().is_none().then_some(expr)
+}
+
+type Tokens<'a> = Peekable<vec::IntoIter<&'a str>>;
+
+/// sum = product (('+' | '-') product)*
+fn parse_sum(tokens: &mut Tokens) -> Option<Expr> {
+    let mut expr = parse_product(tokens)?;
+    while let Some(op) = tokens.next_if(|&t| t == "+" || t == "-") {
+        let op = if op == "+" { BinOp::Add } else { BinOp::Sub };
+        expr = Expr::Binary(Box::new(expr), op, Box::new(parse_product(tokens)?));
+    }
+    Some(expr)
+}
+
+/// product = atom (('*' | '/') atom)*
+fn parse_product(tokens: &mut Tokens) -> Option<Expr> {
+    let mut expr = parse_atom(tokens)?;
+    while let Some(op) = tokens.next_if(|&t| t == "*" || t == "/") {
+        let op = if op == "*" { BinOp::Mul } else { BinOp::Div };
+        expr = Expr::Binary(Box::new(expr), op, Box::new(parse_atom(tokens)?));
+    }
+    Some(expr)
+}
+
+/// atom = 'old' | number | '(' sum ')' | '-' atom
+fn parse_atom(tokens: &mut Tokens) -> Option<Expr> {
+    match tokens.next()? {
+        "old" => Some(Expr::Old),
+        "-" => Some(match parse_atom(tokens)? {
+            Expr::Const(n) => Expr::Const(n.checked_neg()?),
+            expr => Expr::Binary(Box::new(Expr::Const(0)), BinOp::Sub, Box::new(expr)),
+        }),
+        "(" => {
+            let expr = parse_sum(tokens)?;
+            tokens.next_if_eq(&")")?;
+            Some(expr)
+        }
+        number => number.parse().ok().map(Expr::Const),
+    }
+}
+
+/// Panics if the input is malformed, or if a monkey throws to itself or tests divisibility by zero
 pub fn parse(input: &str) -> Vec<Monkey> {
     input
         .split("\n\n")
-        .map(|monkey| {
+        .enumerate()
+        .map(|(id, monkey)| {
             let [_, starting_items, operation, test, if_true, if_false] =
                 monkey.lines().array_chunks().next().unwrap();
 
@@ -28,18 +268,12 @@ pub fn parse(input: &str) -> Vec<Monkey> {
                 .collect();
 
             let operation = operation.strip_prefix("  Operation: new = ").unwrap();
-            let operation = if operation == "old * old" {
-                Operation::Square
-            } else if let Some(mul) = operation.strip_prefix("old * ") {
-                Operation::Mul(mul.parse().unwrap())
-            } else if let Some(add) = operation.strip_prefix("old + ") {
-                Operation::Add(add.parse().unwrap())
-            } else {
-                panic!()
-            };
+            let operation = Operation::parse(operation)
+                .unwrap_or_else(|| panic!("invalid operation: {operation}"));
 
             let test_div_by = test.strip_prefix("  Test: divisible by ").unwrap();
             let test_div_by = test_div_by.parse().unwrap();
+            assert_ne!(test_div_by, 0, "monkey {id} tests divisibility by zero");
 
             let if_true = if_true
                 .strip_prefix("    If true: throw to monkey ")
@@ -51,6 +285,10 @@ pub fn parse(input: &str) -> Vec<Monkey> {
                 .unwrap()
                 .parse()
                 .unwrap();
+            assert!(
+                if_true != id && if_false != id,
+                "monkey {id} throws to itself"
+            );
 
             Monkey {
                 items,
@@ -64,57 +302,310 @@ pub fn parse(input: &str) -> Vec<Monkey> {
 }
 
 pub fn part1(input: &str) -> u64 {
-    let mut monkeys = parse(input);
-    monkey_business(&mut monkeys, 20, |item| item / 3)
+    let monkeys = parse(input);
+    monkey_business(&monkeys, 20, |item: i64| Some(item / 3)).unwrap_or_else(|e| panic!("{e}"))
 }
 
 pub fn part2(input: &str) -> u64 {
-    let mut monkeys = parse(input);
-    let test_product: i64 = monkeys.iter().map(|monkey| monkey.test_div_by).product();
-    monkey_business(&mut monkeys, 10000, |item| item % test_product)
+    let monkeys = parse(input);
+    top_two_product(inspections(&monkeys, 10000))
+}
+
+/// An item whose worry level couldn't be computed when a monkey inspected it
+#[derive(Debug, PartialEq, Eq)]
+pub struct InspectionError<W> {
+    /// Whether the worry level got too big for the worry type, or was divided by zero
+    pub kind: OperationError,
+
+    /// The round it happened in, starting at 1
+    pub round: usize,
+
+    /// The monkey that was inspecting the item
+    pub monkey: usize,
+
+    /// The worry level of the item before it was inspected
+    pub worry: W,
+}
+
+pub fn monkey_business<W: Worry>(
+    monkeys: &[Monkey],
+    rounds: usize,
+    decrease_worry: impl Fn(W) -> Option<W>,
+) -> Result<u64, InspectionError<W>> {
+    activity(monkeys, rounds, decrease_worry).map(top_two_product)
+}
+
+fn top_two_product(mut activity: Vec<u64>) -> u64 {
+    activity.sort();
+    activity.reverse();
+    activity[0] * activity[1]
+}
+
+/// Simulate the monkeys round by round, and count how many items each monkey inspects
+pub fn activity<W: Worry>(
+    monkeys: &[Monkey],
+    rounds: usize,
+    decrease_worry: impl Fn(W) -> Option<W>,
+) -> Result<Vec<u64>, InspectionError<W>> {
+    simulate(monkeys, rounds, decrease_worry, &mut ())
+}
+
+/// A monkey inspecting an item and throwing it to another monkey
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Inspection<W> {
+    /// The current round, starting at 1
+    pub round: usize,
+    pub monkey: usize,
+
+    /// The worry level before the inspection
+    pub old: W,
+
+    /// The worry level after the inspection, once my worry has decreased
+    pub new: W,
+
+    /// The monkey that the item is thrown to
+    pub target: usize,
 }
 
-pub fn monkey_business(
-    monkeys: &mut [Monkey],
+/// Watches the monkeys while they are being simulated
+pub trait Observer<W> {
+    fn inspect(&mut self, _inspection: Inspection<W>) {}
+
+    /// Called after every round, with the items held by every monkey
+    fn end_of_round(&mut self, _round: usize, _items: &[VecDeque<W>]) {}
+}
+
+impl<W> Observer<W> for () {}
+
+/// Records every inspection
+impl<W> Observer<W> for Vec<Inspection<W>> {
+    fn inspect(&mut self, inspection: Inspection<W>) {
+        self.push(inspection);
+    }
+}
+
+/// Lists the items held by every monkey after each round, like in the puzzle description
+#[derive(Default)]
+pub struct HoldingsReport {
+    pub text: String,
+}
+
+impl<W: Display> Observer<W> for HoldingsReport {
+    fn end_of_round(&mut self, round: usize, items: &[VecDeque<W>]) {
+        if round > 1 {
+            self.text.push('\n');
+        }
+
+        let text = &mut self.text;
+        writeln!(
+            text,
+            "After round {round}, the monkeys are holding items with these worry levels:"
+        )
+        .unwrap();
+        for (i, items) in items.iter().enumerate() {
+            write!(text, "Monkey {i}: ").unwrap();
+            for (j, item) in items.iter().enumerate() {
+                let separator = if j == 0 { "" } else { ", " };
+                write!(text, "{separator}{item}").unwrap();
+            }
+            text.push('\n');
+        }
+    }
+}
+
+/// Simulate the monkeys round by round, telling the observer about everything that happens
+pub fn simulate<W: Worry>(
+    monkeys: &[Monkey],
     rounds: usize,
-    decrease_worry: impl Fn(i64) -> i64,
-) -> u64 {
+    decrease_worry: impl Fn(W) -> Option<W>,
+    observer: &mut impl Observer<W>,
+) -> Result<Vec<u64>, InspectionError<W>> {
+    let mut items: Vec<VecDeque<W>> = (monkeys.iter())
+        .map(|monkey| monkey.items.iter().map(|&item| W::from_i64(item)).collect())
+        .collect();
+
     let mut activity = vec![0; monkeys.len()];
-    for _round in 0..rounds {
-        for i in 0..monkeys.len() {
-            activity[i] += monkeys[i].items.len() as u64;
-            while let Some(mut item) = monkeys[i].items.pop_front() {
-                // monkey inspects item
-                match monkeys[i].operation {
-                    Operation::Square => item *= item,
-                    Operation::Mul(mul) => item *= mul,
-                    Operation::Add(add) => item += add,
-                }
-
-                // my worry decreases
-                item = decrease_worry(item);
+    for round in 1..=rounds {
+        for (i, monkey) in monkeys.iter().enumerate() {
+            activity[i] += items[i].len() as u64;
+            while let Some(item) = items[i].pop_front() {
+                // monkey inspects item, and my worry decreases
+                let new_item = (monkey.operation.apply(&item))
+                    .and_then(|item| decrease_worry(item).ok_or(OperationError::Overflow));
+                let new_item = match new_item {
+                    Ok(new_item) => new_item,
+                    Err(kind) => {
+                        return Err(InspectionError {
+                            kind,
+                            round,
+                            monkey: i,
+                            worry: item,
+                        })
+                    }
+                };
 
                 // monkey tests item
-                let throw_to = if item % monkeys[i].test_div_by == 0 {
-                    monkeys[i].if_true
+                let throw_to = if new_item.rem_i64(monkey.test_div_by) == 0 {
+                    monkey.if_true
                 } else {
-                    monkeys[i].if_false
+                    monkey.if_false
                 };
 
+                observer.inspect(Inspection {
+                    round,
+                    monkey: i,
+                    old: item,
+                    new: new_item.clone(),
+                    target: throw_to,
+                });
+
                 // monkey throws item
-                monkeys[throw_to].items.push_back(item);
+                items[throw_to].push_back(new_item);
             }
         }
+
+        observer.end_of_round(round, &items);
     }
 
-    activity.sort();
-    activity.reverse();
-    activity[0] * activity[1]
+    Ok(activity)
+}
+
+/// Count how many items each monkey inspects, with worry levels kept modulo the product of the
+/// test divisors. Operations that would overflow an `i128` fall back to bignums.
+///
+/// Items move independently of each other, so every item is simulated on its own, in parallel.
+/// The state of an item between rounds is the monkey holding it and its worry level, which must
+/// eventually repeat. Once it does, the rest of the rounds are skipped a whole cycle at a time.
+pub fn inspections(monkeys: &[Monkey], rounds: u64) -> Vec<u64> {
+    assert!(
+        (monkeys.iter()).all(|monkey| !monkey.operation.divides()),
+        "division doesn't work modulo the test product"
+    );
+
+    let modulus = (monkeys.iter())
+        .try_fold(1i128, |product, monkey| {
+            product.checked_mul(monkey.test_div_by as i128)
+        })
+        .expect("the product of the test divisors overflowed");
+    let items: Vec<ItemState> = (monkeys.iter().enumerate())
+        .flat_map(|(i, monkey)| monkey.items.iter().map(move |&item| (i, item as i128)))
+        .map(|(i, worry)| (i, worry.rem_euclid(modulus)))
+        .collect();
+
+    items
+        .into_par_iter()
+        .map(|item| item_inspections(monkeys, modulus, item, rounds))
+        .reduce(
+            || vec![0; monkeys.len()],
+            |mut total, counts| {
+                total.iter_mut().zip(counts).for_each(|(t, c)| *t += c);
+                total
+            },
+        )
+}
+
+/// The monkey holding an item and its worry level, at the start of a round
+type ItemState = (usize, i128);
+
+fn item_inspections(monkeys: &[Monkey], modulus: i128, item: ItemState, rounds: u64) -> Vec<u64> {
+    let mut counts = vec![0; monkeys.len()];
+    let mut seen = HashMap::default();
+    let mut state = item;
+    let mut round = 0;
+
+    while round < rounds {
+        let Some(cycle_start) = seen.insert(state, round) else {
+            state = item_round(monkeys, modulus, state, &mut counts);
+            round += 1;
+            continue;
+        };
+
+        // go around the cycle once more to count its inspections
+        let cycle_len = round - cycle_start;
+        let mut cycle_counts = vec![0; monkeys.len()];
+        for _ in 0..cycle_len {
+            state = item_round(monkeys, modulus, state, &mut cycle_counts);
+        }
+
+        let cycles = (rounds - round) / cycle_len;
+        for (count, cycle_count) in counts.iter_mut().zip(cycle_counts) {
+            *count += cycle_count * cycles;
+        }
+
+        for _ in round + cycles * cycle_len..rounds {
+            state = item_round(monkeys, modulus, state, &mut counts);
+        }
+        break;
+    }
+
+    counts
 }
 
+/// Move an item through a single round, counting the inspections
+fn item_round(
+    monkeys: &[Monkey],
+    modulus: i128,
+    (mut holder, mut worry): ItemState,
+    counts: &mut [u64],
+) -> ItemState {
+    loop {
+        let monkey = &monkeys[holder];
+        counts[holder] += 1;
+        worry = match monkey.operation.apply(&worry) {
+            Ok(new) => new.rem_euclid(modulus),
+
+            // the worry level only matters modulo the test product, so redo the operation with a
+            // bignum and bring it back into range
+            Err(_) => {
+                let new = (monkey.operation.apply(&BigInt::from(worry)))
+                    .expect("operations without division can't fail on bignums");
+                let modulus = BigInt::from(modulus);
+                i128::try_from((new % &modulus + &modulus) % modulus).unwrap()
+            }
+        };
+
+        let throw_to = if worry % monkey.test_div_by as i128 == 0 {
+            monkey.if_true
+        } else {
+            monkey.if_false
+        };
+
+        // monkeys later in the round will inspect the item again in the same round
+        if throw_to <= holder {
+            return (throw_to, worry);
+        }
+        holder = throw_to;
+    }
+}
+
+impl<W: Display> Display for InspectionError<W> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let InspectionError {
+            kind,
+            round,
+            monkey,
+            worry,
+        } = self;
+        let problem = match kind {
+            OperationError::Overflow => "overflowed",
+            OperationError::DivisionByZero => "was divided by zero",
+        };
+        write!(
+            f,
+            "round {round}: worry level {worry} {problem} when inspected by monkey {monkey}"
+        )
+    }
+}
+
+impl<W: Debug + Display> std::error::Error for InspectionError<W> {}
+
 #[cfg(test)]
 mod tests {
-    use super::{part1, part2};
+    use super::{
+        activity, inspections, monkey_business, parse, parse_expr, part1, part2, simulate, BinOp,
+        Expr, HoldingsReport, Inspection, InspectionError, Operation, OperationError, Worry,
+    };
+    use num_bigint::BigInt;
 
     #[test]
     pub fn test_part1() {
@@ -127,4 +618,231 @@ mod tests {
         let input = include_str!("test-input");
         assert_eq!(part2(input), 2713310158);
     }
+
+    #[test]
+    pub fn test_operations() {
+        let op = |s| Operation::parse(s).unwrap();
+        assert!(matches!(op("old * 19"), Operation::Mul(19)));
+        assert!(matches!(op("3 * old"), Operation::Mul(3)));
+        assert!(matches!(op("(old + 6)"), Operation::Add(6)));
+        assert!(matches!(op("old*old"), Operation::Square));
+        assert!(matches!(op("old * -3"), Operation::Mul(-3)));
+        assert!(matches!(op("old + -3"), Operation::Add(-3)));
+        assert!(matches!(op("-3*old"), Operation::Mul(-3)));
+
+        let expr = parse_expr("(old + 2) * (7 - old) / 3").unwrap();
+        assert_eq!(expr.eval(&4i64), Ok(6));
+        assert!(matches!(op("(old + 2) * (7 - old) / 3"), Operation::Expr(e) if e == expr));
+
+        // precedence and associativity
+        assert_eq!(parse_expr("old + 2 * 3").unwrap().eval(&1i64), Ok(7));
+        assert_eq!(parse_expr("old - 2 - 3").unwrap().eval(&10i64), Ok(5));
+        assert_eq!(parse_expr("old / 2 * 3").unwrap().eval(&10i64), Ok(15));
+        assert_eq!(parse_expr("-old * 2").unwrap().eval(&10i64), Ok(-20));
+        assert_eq!(parse_expr("old - -(old - 1)").unwrap().eval(&10i64), Ok(19));
+        assert_eq!(
+            parse_expr("old - old").unwrap(),
+            Expr::Binary(Box::new(Expr::Old), BinOp::Sub, Box::new(Expr::Old))
+        );
+
+        for invalid in [
+            "", "old +", "(old", "old)", "old 2", "new * 2", "old % 2", "old * -",
+        ] {
+            assert_eq!(parse_expr(invalid), None, "{invalid:?}");
+        }
+
+        // the example, but with a more complicated operation for monkey 3
+        let input = include_str!("test-input").replace("old + 3", "(old * 2 + 6) / 2");
+        assert_eq!(part1(&input), 10605);
+        let input = include_str!("test-input").replace("old + 3", "old - -3");
+        assert_eq!(part1(&input), 10605);
+    }
+
+    #[test]
+    pub fn test_worry_types() {
+        let monkeys = parse(include_str!("test-input"));
+        let part1_i128 = monkey_business(&monkeys, 20, |item: i128| Some(item / 3));
+        let part1_big = monkey_business(&monkeys, 20, |item: BigInt| Some(item / 3));
+        assert_eq!(part1_i128, Ok(10605));
+        assert_eq!(part1_big, Ok(10605));
+
+        // without the modulo trick, worry levels grow without bound
+        let error = monkey_business(&monkeys, 10000, Some::<i64>).unwrap_err();
+        let error_i128 = monkey_business(&monkeys, 10000, Some::<i128>).unwrap_err();
+        assert!(error_i128.round > error.round);
+        assert_eq!(
+            error.to_string(),
+            format!(
+                "round {}: worry level {} overflowed when inspected by monkey {}",
+                error.round, error.worry, error.monkey
+            )
+        );
+
+        // the reported item really does overflow
+        let operation = &monkeys[error.monkey].operation;
+        let worry = operation.apply(&BigInt::from(error.worry)).unwrap();
+        assert!(worry > BigInt::from(i64::MAX));
+        assert_eq!(error.kind, OperationError::Overflow);
+        assert_eq!(operation.apply(&error.worry), Err(OperationError::Overflow));
+
+        // the same rounds give the same answer with bignums
+        let rounds = error.round - 1;
+        let small = monkey_business(&monkeys, rounds, Some::<i64>).unwrap();
+        let big = monkey_business(&monkeys, rounds, Some::<BigInt>).unwrap();
+        assert_eq!(small, big);
+        assert!(monkey_business(&monkeys, error_i128.round + 10, Some::<BigInt>).is_ok());
+
+        // division by zero
+        let input = include_str!("test-input").replace("old + 3", "old / (old - old)");
+        let monkeys = parse(&input);
+        let error = monkey_business(&monkeys, 20, |item: BigInt| Some(item / 3)).unwrap_err();
+        assert_eq!(
+            error,
+            InspectionError {
+                kind: OperationError::DivisionByZero,
+                round: 1,
+                monkey: 3,
+                worry: BigInt::from(74)
+            }
+        );
+        assert_eq!(
+            error.to_string(),
+            "round 1: worry level 74 was divided by zero when inspected by monkey 3"
+        );
+        assert_eq!(BigInt::from(-7).rem_i64(3), -1);
+    }
+
+    #[test]
+    pub fn test_inspections() {
+        let monkeys = parse(include_str!("test-input"));
+        let test_product: i64 = monkeys.iter().map(|monkey| monkey.test_div_by).product();
+        for rounds in [0, 1, 2, 20, 1000, 10000, 100000] {
+            let expected = activity(&monkeys, rounds, |item: i64| Some(item % test_product));
+            assert_eq!(inspections(&monkeys, rounds as u64), expected.unwrap());
+        }
+
+        let rounds = 10u64.pow(12);
+        let counts = inspections(&monkeys, rounds);
+        assert_eq!(
+            counts,
+            [5217653508757, 4782346491239, 193256578955, 5202028508760]
+        );
+
+        // every item is inspected at least once per round, at roughly the same rate as before
+        let items = monkeys
+            .iter()
+            .map(|monkey| monkey.items.len() as u64)
+            .sum::<u64>();
+        assert!(counts.iter().sum::<u64>() >= rounds * items);
+        for (count, small) in counts.iter().zip(inspections(&monkeys, 100000)) {
+            let rate = *count as f64 / rounds as f64;
+            assert!((rate - small as f64 / 100000.0).abs() < 0.001);
+        }
+
+        // subtraction works modulo the test product, but division does not
+        let input = include_str!("test-input").replace("old + 3", "old - 3 + 6");
+        let monkeys = parse(&input);
+        assert_eq!(inspections(&monkeys, 10000), [52166, 47830, 1938, 52013]);
+    }
+
+    #[test]
+    pub fn test_inspections_overflow() {
+        // the product of the divisors fits in an i128, but squaring a worry level below it doesn't
+        let monkey = |id, divisor, target| {
+            format!(
+                "Monkey {id}:\n  Starting items: 79, 98\n  Operation: new = old * old\n  \
+                 Test: divisible by {divisor}\n    If true: throw to monkey {target}\n    \
+                 If false: throw to monkey {target}"
+            )
+        };
+        let input = [monkey(0, 1099511627791u64, 1), monkey(1, 1099511627689, 0)].join("\n\n");
+        for input in [
+            input.clone(),
+            input.replacen("old * old", "old * old + 1", 1),
+        ] {
+            let monkeys = parse(&input);
+            let expected = activity(&monkeys, 6, Some::<BigInt>).unwrap();
+            assert_eq!(inspections(&monkeys, 6), expected);
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "the product of the test divisors overflowed")]
+    pub fn test_divisor_product_overflow() {
+        let input = include_str!("test-input")
+            .replace("by 23", &format!("by {}", i64::MAX))
+            .replace("by 19", &format!("by {}", i64::MAX))
+            .replace("by 13", &format!("by {}", i64::MAX));
+        inspections(&parse(&input), 1);
+    }
+
+    #[test]
+    #[should_panic(expected = "monkey 1 tests divisibility by zero")]
+    pub fn test_zero_divisor() {
+        parse(&include_str!("test-input").replace("by 19", "by 0"));
+    }
+
+    #[test]
+    #[should_panic(expected = "monkey 0 throws to itself")]
+    pub fn test_self_throw() {
+        parse(&include_str!("test-input").replacen("monkey 2", "monkey 0", 1));
+    }
+
+    #[test]
+    pub fn test_observers() {
+        let monkeys = parse(include_str!("test-input"));
+
+        let mut trace = vec![];
+        let relief = |item: i64| Some(item / 3);
+        let activity = simulate(&monkeys, 20, relief, &mut trace).unwrap();
+        assert_eq!(activity, [101, 95, 7, 105]);
+        assert_eq!(trace.len() as u64, activity.iter().sum::<u64>());
+        assert_eq!(
+            trace[..3],
+            [
+                Inspection {
+                    round: 1,
+                    monkey: 0,
+                    old: 79,
+                    new: 500,
+                    target: 3,
+                },
+                Inspection {
+                    round: 1,
+                    monkey: 0,
+                    old: 98,
+                    new: 620,
+                    target: 3,
+                },
+                Inspection {
+                    round: 1,
+                    monkey: 1,
+                    old: 54,
+                    new: 20,
+                    target: 0,
+                },
+            ]
+        );
+
+        let mut report = HoldingsReport::default();
+        simulate(&monkeys, 20, relief, &mut report).unwrap();
+        let rounds: Vec<&str> = report.text.split("\n\n").collect();
+        assert_eq!(rounds.len(), 20);
+        assert_eq!(
+            rounds[0],
+            "After round 1, the monkeys are holding items with these worry levels:\n\
+             Monkey 0: 20, 23, 27, 26\n\
+             Monkey 1: 2080, 25, 167, 207, 401, 1046\n\
+             Monkey 2: \n\
+             Monkey 3: "
+        );
+        assert_eq!(
+            rounds[19],
+            "After round 20, the monkeys are holding items with these worry levels:\n\
+             Monkey 0: 10, 12, 14, 26, 34\n\
+             Monkey 1: 245, 93, 53, 199, 115\n\
+             Monkey 2: \n\
+             Monkey 3: \n"
+        );
+    }
 }
@@ -2,7 +2,7 @@ use std::collections::BTreeSet;
 
 use crate::util::HashMap;
 
-#[derive(Clone, Copy, Hash, PartialEq, Eq, PartialOrd, Ord)]
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq, PartialOrd, Ord)]
 pub struct Pos {
     x: i32,
     y: i32,
@@ -63,6 +63,55 @@ pub fn part2(input: &str) -> u32 {
         .expect("no path found")
 }
 
+impl Map {
+    /// The shortest path from S to E, including both ends
+    pub fn path_from_start(&self) -> Option<Vec<Pos>> {
+        shortest_path(&self.heights, self.start, self.finish)
+    }
+
+    /// The shortest path to E from any square at elevation a, including both ends
+    pub fn path_from_best_start(&self) -> Option<Vec<Pos>> {
+        self.heights
+            .iter()
+            .filter(|(_, &height)| height == 0)
+            .filter_map(|(&pos, _)| shortest_path(&self.heights, pos, self.finish))
+            .min_by_key(|path| (path.len(), path[0]))
+    }
+
+    /// Draw a path on the map, where every step is an arrow pointing to the next one, like in
+    /// the puzzle description
+    pub fn render_path(&self, path: &[Pos]) -> String {
+        let width = self.heights.keys().map(|pos| pos.x + 1).max().unwrap_or(0);
+        let height = self.heights.keys().map(|pos| pos.y + 1).max().unwrap_or(0);
+
+        let mut steps = HashMap::default();
+        for step in path.windows(2) {
+            let arrow = match (step[1].x - step[0].x, step[1].y - step[0].y) {
+                (1, 0) => '>',
+                (-1, 0) => '<',
+                (0, 1) => 'v',
+                (0, -1) => '^',
+                _ => panic!("path isn't continuous"),
+            };
+            steps.insert(step[0], arrow);
+        }
+
+        let mut out = String::with_capacity((width as usize + 1) * height as usize);
+        for y in 0..height {
+            for x in 0..width {
+                let pos = Pos { x, y };
+                out.push(match steps.get(&pos) {
+                    Some(&arrow) => arrow,
+                    None if pos == self.finish => 'E',
+                    None => '.',
+                });
+            }
+            out.push('\n');
+        }
+        out
+    }
+}
+
 impl Pos {
     fn manhattan_dist(&self, to: &Pos) -> u32 {
         self.x.abs_diff(to.x) + self.y.abs_diff(to.y)
@@ -77,6 +126,10 @@ impl Pos {
 }
 
 fn shortest_path_distance(heights: &HashMap<Pos, u8>, start: Pos, finish: Pos) -> Option<u32> {
+    shortest_path(heights, start, finish).map(|path| path.len() as u32 - 1)
+}
+
+fn shortest_path(heights: &HashMap<Pos, u8>, start: Pos, finish: Pos) -> Option<Vec<Pos>> {
     let mut open_set = BTreeSet::new();
     open_set.insert((start.manhattan_dist(&finish), start));
 
@@ -94,12 +147,18 @@ fn shortest_path_distance(heights: &HashMap<Pos, u8>, start: Pos, finish: Pos) -
 
     while let Some((_estimated_dist, current)) = open_set.pop_first() {
         if current == finish {
-            return Some(f_score[&current]);
+            // walk back along the path
+            let mut path = vec![current];
+            while let Some(&previous) = came_from.get(path.last().unwrap()) {
+                path.push(previous);
+            }
+            path.reverse();
+            return Some(path);
         }
 
         for neighbor in current
             .neighbors()
-            .filter(|neighbor| heights.contains_key(&neighbor))
+            .filter(|neighbor| heights.contains_key(neighbor))
             .filter(|neighbor| heights[neighbor] as i8 - heights[&current] as i8 <= 1)
         {
             let tentative_g_score = g_score.get(&current).map(|s| s + 1).unwrap_or(u32::MAX);
@@ -120,7 +179,7 @@ fn shortest_path_distance(heights: &HashMap<Pos, u8>, start: Pos, finish: Pos) -
 
 #[cfg(test)]
 mod tests {
-    use super::{part1, part2};
+    use super::{parse, part1, part2};
 
     #[test]
     pub fn test_part1() {
@@ -133,4 +192,32 @@ mod tests {
         let input = include_str!("test-input");
         assert_eq!(part2(input), 29);
     }
+
+    #[test]
+    pub fn test_render_path() {
+        let map = parse(include_str!("test-input"));
+
+        let path = map.path_from_start().unwrap();
+        assert_eq!(path.len(), 31 + 1);
+        assert_eq!((path[0], path[31]), (map.start, map.finish));
+        assert_eq!(
+            map.render_path(&path),
+            "v..v<<<<\n\
+             >v.vv<<^\n\
+             .>vv>E^^\n\
+             ..v>>>^^\n\
+             ..>>>>>^\n"
+        );
+
+        let path = map.path_from_best_start().unwrap();
+        assert_eq!(path.len(), 29 + 1);
+        assert_eq!(
+            map.render_path(&path),
+            "...v<<<<\n\
+             ...vv<<^\n\
+             ...v>E^^\n\
+             .>v>>>^^\n\
+             >^>>>>>^\n"
+        );
+    }
 }
@@ -0,0 +1,9 @@
+//! The day modules which the tools binary needs, built as a library so that it can link to them.
+//!
+//! The runner binary compiles every day on its own, and is where their tests and benchmarks run.
+
+#[allow(dead_code)]
+mod util;
+
+pub mod day05;
+pub mod day07;
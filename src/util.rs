@@ -22,3 +22,170 @@ pub fn get_2_mut<T>(slice: &mut [T], first: usize, second: usize) -> [&mut T; 2]
         ]
     }
 }
+
+/// Encode an 8-bit grayscale image as a binary PGM
+pub fn encode_pgm(width: usize, height: usize, pixels: &[u8]) -> Vec<u8> {
+    assert_eq!(pixels.len(), width * height);
+    let mut out = format!("P5\n{width} {height}\n255\n").into_bytes();
+    out.extend_from_slice(pixels);
+    out
+}
+
+/// Encode a black and white image as a binary PBM, where `true` is white
+pub fn encode_pbm(width: usize, height: usize, pixels: &[bool]) -> Vec<u8> {
+    assert_eq!(pixels.len(), width * height);
+    let mut out = format!("P4\n{width} {height}\n").into_bytes();
+    for row in pixels.chunks(width.max(1)).take(height) {
+        // rows are padded to whole bytes, and a set bit is black
+        for byte in row.chunks(8) {
+            let bits = (byte.iter().enumerate())
+                .fold(0u8, |bits, (i, &white)| bits | ((!white as u8) << (7 - i)));
+            out.push(bits);
+        }
+    }
+    out
+}
+
+/// Encode an 8-bit grayscale image as a PNG.
+///
+/// The image data is stored using uncompressed deflate blocks, so the files are large but don't
+/// need a compression library.
+pub fn encode_png(width: usize, height: usize, pixels: &[u8]) -> Vec<u8> {
+    assert_eq!(pixels.len(), width * height);
+
+    let mut out = b"\x89PNG\r\n\x1a\n".to_vec();
+    png_chunk(&mut out, b"IHDR", &png_header(width, height));
+    png_chunk(&mut out, b"IDAT", &png_zlib(width, height, pixels));
+    png_chunk(&mut out, b"IEND", &[]);
+    out
+}
+
+/// Encode a sequence of 8-bit grayscale images as a looping animated PNG.
+///
+/// Every frame is shown for `delay_ms` milliseconds.
+pub fn encode_apng(width: usize, height: usize, frames: &[Vec<u8>], delay_ms: u16) -> Vec<u8> {
+    assert!(!frames.is_empty());
+
+    let mut out = b"\x89PNG\r\n\x1a\n".to_vec();
+    png_chunk(&mut out, b"IHDR", &png_header(width, height));
+
+    let mut animation = vec![];
+    animation.extend_from_slice(&(frames.len() as u32).to_be_bytes());
+    animation.extend_from_slice(&0u32.to_be_bytes()); // loop forever
+    png_chunk(&mut out, b"acTL", &animation);
+
+    // frame control and frame data chunks share a sequence number
+    let mut sequence = 0u32;
+    for (i, pixels) in frames.iter().enumerate() {
+        assert_eq!(pixels.len(), width * height);
+
+        let mut control = vec![];
+        control.extend_from_slice(&sequence.to_be_bytes());
+        control.extend_from_slice(&png_header(width, height)[..8]);
+        control.extend_from_slice(&[0; 8]); // x and y offset
+        control.extend_from_slice(&delay_ms.to_be_bytes());
+        control.extend_from_slice(&1000u16.to_be_bytes());
+        // don't dispose or blend frames
+        control.extend_from_slice(&[0, 0]);
+        png_chunk(&mut out, b"fcTL", &control);
+        sequence += 1;
+
+        // the first frame doubles as the static image
+        let zlib = png_zlib(width, height, pixels);
+        if i == 0 {
+            png_chunk(&mut out, b"IDAT", &zlib);
+        } else {
+            let mut data = sequence.to_be_bytes().to_vec();
+            data.extend_from_slice(&zlib);
+            png_chunk(&mut out, b"fdAT", &data);
+            sequence += 1;
+        }
+    }
+
+    png_chunk(&mut out, b"IEND", &[]);
+    out
+}
+
+fn png_chunk(out: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    out.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    let start = out.len();
+    out.extend_from_slice(kind);
+    out.extend_from_slice(data);
+    let crc = crc32(&out[start..]);
+    out.extend_from_slice(&crc.to_be_bytes());
+}
+
+fn png_header(width: usize, height: usize) -> Vec<u8> {
+    let mut header = vec![];
+    header.extend_from_slice(&(width as u32).to_be_bytes());
+    header.extend_from_slice(&(height as u32).to_be_bytes());
+    // bit depth 8, grayscale, deflate, adaptive filtering, no interlace
+    header.extend_from_slice(&[8, 0, 0, 0, 0]);
+    header
+}
+
+/// Compress the image data of an 8-bit grayscale image
+fn png_zlib(width: usize, height: usize, pixels: &[u8]) -> Vec<u8> {
+    // every scanline is prefixed with filter type 0 (none)
+    let mut raw = Vec::with_capacity((width + 1) * height);
+    for row in pixels.chunks(width.max(1)).take(height) {
+        raw.push(0);
+        raw.extend_from_slice(row);
+    }
+
+    // zlib stream of stored deflate blocks
+    let mut zlib = vec![0x78, 0x01];
+    let mut blocks = raw.chunks(u16::MAX as usize).peekable();
+    if blocks.peek().is_none() {
+        zlib.extend_from_slice(&[1, 0, 0, 0xff, 0xff]);
+    }
+    while let Some(block) = blocks.next() {
+        let last = blocks.peek().is_none();
+        let len = block.len() as u16;
+        zlib.push(last as u8);
+        zlib.extend_from_slice(&len.to_le_bytes());
+        zlib.extend_from_slice(&(!len).to_le_bytes());
+        zlib.extend_from_slice(block);
+    }
+    zlib.extend_from_slice(&adler32(&raw).to_be_bytes());
+    zlib
+}
+
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ 0xedb88320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for &byte in data {
+        a = (a + byte as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+    (b << 16) | a
+}
+
+/// A tiny linear congruential generator, for reproducible pseudo-random test inputs
+#[cfg(test)]
+pub struct Lcg(pub u64);
+
+#[cfg(test)]
+impl Lcg {
+    /// A pseudo-random number in `0..n`
+    pub fn below(&mut self, n: u64) -> u64 {
+        self.0 = (self.0)
+            .wrapping_mul(6364136223846793005)
+            .wrapping_add(1442695040888963407);
+        (self.0 >> 33) % n
+    }
+}